@@ -0,0 +1,125 @@
+//! Compares the `DatadogFormatter` with a formatter serializing events with `serde_json`, as it
+//! did before writing JSON directly.
+use std::io;
+
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, Criterion};
+use datadog_tracing::formatter::{DatadogFormatter, LogLayout};
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId, TracerProvider as _};
+use opentelemetry_sdk::trace::TracerProvider;
+use serde::ser::{SerializeMap, Serializer as _};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_serde::AsSerde;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Registry;
+
+struct SerdeFormatter;
+
+impl<S, N> FormatEvent<S, N> for SerdeFormatter
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let meta = event.metadata();
+        let mut visit = || {
+            let mut serializer = serde_json::Serializer::new(WriteAdaptor(&mut writer));
+            let mut serializer = serializer.serialize_map(None)?;
+            serializer.serialize_entry("timestamp", &Utc::now().to_rfc3339())?;
+            serializer.serialize_entry("level", &meta.level().as_serde())?;
+            serializer.serialize_entry("target", meta.target())?;
+            let mut visitor = tracing_serde::SerdeMapVisitor::new(serializer);
+            event.record(&mut visitor);
+            serializer = visitor.take_serializer()?;
+            if let Some(span_ref) = ctx.lookup_current() {
+                if let Some(otel_data) = span_ref.extensions().get::<OtelData>() {
+                    let trace_id = if otel_data.parent_cx.has_active_span() {
+                        otel_data.parent_cx.span().span_context().trace_id()
+                    } else {
+                        otel_data.builder.trace_id.unwrap_or(TraceId::INVALID)
+                    };
+                    let span_id = otel_data.builder.span_id.unwrap_or(SpanId::INVALID);
+                    let trace_id = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+                    serializer
+                        .serialize_entry("dd.span_id", &u64::from_be_bytes(span_id.to_bytes()))?;
+                    serializer.serialize_entry("dd.trace_id", &trace_id)?;
+                }
+            }
+            serializer.end()
+        };
+        visit().map_err(|_| std::fmt::Error)?;
+        writeln!(writer)
+    }
+}
+
+struct WriteAdaptor<'a, 'w>(&'a mut Writer<'w>);
+
+impl io::Write for WriteAdaptor<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s =
+            std::str::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.0.write_str(s).map_err(io::Error::other)?;
+        Ok(s.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn bench_formatter<F>(c: &mut Criterion, name: &str, formatter: F)
+where
+    F: for<'w> FormatEvent<Registry, tracing_subscriber::fmt::format::JsonFields>
+        + Send
+        + Sync
+        + 'static,
+{
+    let provider = TracerProvider::builder().build();
+    let subscriber = Registry::default()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .event_format(formatter)
+                .with_writer(io::sink),
+        )
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("bench")));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("handle_request", request_id = "abc").entered();
+        c.bench_function(name, |b| {
+            b.iter(|| {
+                tracing::info!(
+                    user_id = 42,
+                    latency = 1.5,
+                    path = "/users/{id}",
+                    query = ?("name", "a \"quoted\" value"),
+                    "request handled"
+                )
+            })
+        });
+    });
+}
+
+fn formatter(c: &mut Criterion) {
+    bench_formatter(c, "serde_json", SerdeFormatter);
+    bench_formatter(c, "datadog_formatter", DatadogFormatter::default());
+    bench_formatter(
+        c,
+        "datadog_formatter_datadog_layout",
+        DatadogFormatter::default()
+            .with_service("bench")
+            .with_layout(LogLayout::Datadog),
+    );
+}
+
+criterion_group!(benches, formatter);
+criterion_main!(benches);
@@ -0,0 +1,220 @@
+//! Discovery of the Datadog agent.
+//!
+//! The agent describes its version and the endpoints it supports on its `/info` endpoint,
+//! which [`fetch_info`] queries. When the tracer is built, the Datadog exporter queries it in
+//! the background to select the trace API version, and to enable client side stats and native
+//! span events only if the agent supports them. The first export waits for the discovery, so
+//! no payload is sent to an endpoint the agent doesn't support. If the agent doesn't answer,
+//! the configured settings are used.
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures_util::future::{BoxFuture, Shared};
+use futures_util::FutureExt;
+use serde::Deserialize;
+
+use crate::exporter::{ApiVersion, Error};
+
+/// Path of the agent discovery endpoint.
+pub const INFO_PATH: &str = "/info";
+/// Path of the agent endpoint receiving client side stats.
+pub(crate) const STATS_PATH: &str = "/v0.6/stats";
+
+const INFO_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Agent information returned by its `/info` endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct AgentInfo {
+    /// Version of the agent, e.g. `7.50.0`
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Endpoints supported by the agent, e.g. `/v0.5/traces`
+    #[serde(default)]
+    pub endpoints: Vec<String>,
+    /// Whether the agent accepts that the tracer drops unsampled traces
+    #[serde(default)]
+    pub client_drop_p0s: bool,
+    /// Feature flags enabled on the agent
+    #[serde(default)]
+    pub feature_flags: Vec<String>,
+    /// Whether the agent accepts span events in the `span_events` field of v0.4 spans
+    #[serde(default)]
+    pub span_events: bool,
+}
+
+impl AgentInfo {
+    /// Whether the agent supports the given endpoint, e.g. `/v0.6/stats`.
+    pub fn supports(&self, endpoint: &str) -> bool {
+        self.endpoints.iter().any(|e| e == endpoint)
+    }
+
+    /// The most recent trace API version supported by the agent.
+    pub fn trace_api_version(&self) -> Option<ApiVersion> {
+        [ApiVersion::Version05, ApiVersion::Version04]
+            .into_iter()
+            .find(|version| self.supports(version.path()))
+    }
+}
+
+/// Features of the agent used by the exporters, shared so they can be updated once discovered.
+pub(crate) type SharedFeatures = Arc<RwLock<AgentFeatures>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct AgentFeatures {
+    pub(crate) api_version: ApiVersion,
+    /// Stats are computed by the tracer, see [`crate::exporter::stats`]
+    pub(crate) client_computed_stats: bool,
+    /// Unsampled traces are dropped by the tracer
+    pub(crate) client_drop_p0s: bool,
+    /// Span events are sent in the `span_events` field instead of the `events` tag
+    pub(crate) span_events: bool,
+}
+
+impl AgentFeatures {
+    /// Resolves the features from the configuration and, when available, the agent info.
+    ///
+    /// An explicit API version is always honored. Without agent info, the default API version
+    /// and the configured stats computation are used.
+    pub(crate) fn resolve(
+        api_version: Option<ApiVersion>,
+        stats_computation: bool,
+        info: Option<&AgentInfo>,
+    ) -> Self {
+        let client_computed_stats =
+            stats_computation && info.is_none_or(|info| info.supports(STATS_PATH));
+        AgentFeatures {
+            api_version: api_version
+                .or_else(|| info.and_then(AgentInfo::trace_api_version))
+                .unwrap_or_default(),
+            client_computed_stats,
+            // unsampled traces are lost if the agent doesn't expect them to be dropped
+            client_drop_p0s: client_computed_stats && info.is_some_and(|info| info.client_drop_p0s),
+            span_events: info.is_some_and(|info| info.span_events),
+        }
+    }
+}
+
+/// Discovery of the agent, shared by the exporter and the startup logs. It completes within
+/// the timeout of the `/info` request.
+pub(crate) type AgentDiscovery = Shared<BoxFuture<'static, Result<AgentInfo, Arc<Error>>>>;
+
+/// Queries the agent info, and updates the shared features from it.
+///
+/// Nothing is sent until the returned future is polled, e.g. once spawned.
+pub(crate) fn discover(
+    client: reqwest::Client,
+    agent_endpoint: String,
+    api_version: Option<ApiVersion>,
+    stats_computation: bool,
+    features: SharedFeatures,
+) -> AgentDiscovery {
+    async move {
+        let info = fetch_info(&client, &agent_endpoint).await;
+        match &info {
+            Ok(info) => {
+                let discovered = AgentFeatures::resolve(api_version, stats_computation, Some(info));
+                tracing::debug!(features = ?discovered, "discovered datadog agent features");
+                *features.write().unwrap() = discovered;
+            }
+            Err(err) => {
+                tracing::debug!(error = %err, "datadog agent info unavailable, using the configuration");
+            }
+        }
+        info.map_err(Arc::new)
+    }
+    .boxed()
+    .shared()
+}
+
+/// Queries the `/info` endpoint of the agent at `agent_endpoint`, e.g. `http://localhost:8126`.
+pub async fn fetch_info(
+    client: &reqwest::Client,
+    agent_endpoint: &str,
+) -> Result<AgentInfo, Error> {
+    let url = format!("{}{INFO_PATH}", agent_endpoint.trim_end_matches('/'));
+    let body = client
+        .get(url)
+        .timeout(INFO_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    serde_json::from_slice(&body).map_err(|err| Error::InvalidResponse(err.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    // starts a mock agent answering `body` on every request, and returns its endpoint
+    pub(crate) fn mock_agent(body: &'static str) -> String {
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |_: Request<Body>| async move {
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn test_fetch_info() {
+        let endpoint = mock_agent(
+            r#"{"version":"7.50.0","endpoints":["/v0.4/traces","/v0.5/traces","/v0.6/stats"],
+                "client_drop_p0s":true,"config":{"default_env":"none"}}"#,
+        );
+
+        let info = fetch_info(&reqwest::Client::new(), &endpoint)
+            .await
+            .unwrap();
+
+        assert_eq!(info.version.as_deref(), Some("7.50.0"));
+        assert!(info.supports("/v0.6/stats"));
+        assert!(!info.supports("/v0.7/traces"));
+        assert!(info.client_drop_p0s);
+        assert!(info.feature_flags.is_empty());
+    }
+
+    #[test]
+    fn test_resolve_features() {
+        let info = AgentInfo {
+            endpoints: vec!["/v0.4/traces".to_string()],
+            span_events: true,
+            ..Default::default()
+        };
+
+        let features = AgentFeatures::resolve(None, true, Some(&info));
+        assert_eq!(features.api_version, ApiVersion::Version04);
+        assert!(!features.client_computed_stats);
+        assert!(!features.client_drop_p0s);
+        assert!(features.span_events);
+
+        // the configuration is used without agent info, but unsampled traces are only dropped
+        // once the agent reports it, and an explicit version always wins
+        let features = AgentFeatures::resolve(None, true, None);
+        assert_eq!(features.api_version, ApiVersion::Version05);
+        assert!(features.client_computed_stats);
+        assert!(!features.client_drop_p0s);
+        assert!(!features.span_events);
+        let features = AgentFeatures::resolve(Some(ApiVersion::Version05), false, Some(&info));
+        assert_eq!(features.api_version, ApiVersion::Version05);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_info_invalid_response() {
+        let endpoint = mock_agent("404 page not found");
+
+        let err = fetch_info(&reqwest::Client::new(), &endpoint)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, Error::InvalidResponse(_)));
+    }
+}
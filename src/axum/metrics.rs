@@ -0,0 +1,269 @@
+//! RED (rate, errors, duration) metrics of the requests handled by [`super::OtelAxumLayer`].
+//!
+//! For every request, the layer records:
+//!
+//! - `http.server.requests`: count of requests
+//! - `http.server.errors`: count of requests failing with a `5xx` status or an error
+//! - `http.server.duration`: histogram of the request durations, in seconds
+//!
+//! tagged with `http.route`, `http.request.method` and `http.status_class` (e.g. `2xx`).
+//! Metrics are sent to DogStatsD (with the `metrics` feature) or to an OpenTelemetry [`Meter`].
+use std::fmt::{Debug, Formatter};
+use std::time::Duration;
+
+use opentelemetry::metrics::{Counter, Histogram, Meter, Unit};
+use opentelemetry::KeyValue;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::DogStatsD;
+
+const REQUESTS_METRIC: &str = "http.server.requests";
+const ERRORS_METRIC: &str = "http.server.errors";
+const DURATION_METRIC: &str = "http.server.duration";
+
+#[derive(Clone)]
+enum Sink {
+    #[cfg(feature = "metrics")]
+    DogStatsD(DogStatsD),
+    Meter {
+        requests: Counter<u64>,
+        errors: Counter<u64>,
+        duration: Histogram<f64>,
+    },
+}
+
+/// Destination of the request metrics of [`super::OtelAxumLayer::metrics`].
+#[derive(Clone)]
+pub struct RequestMetrics {
+    sink: Sink,
+}
+
+impl Debug for RequestMetrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sink = match self.sink {
+            #[cfg(feature = "metrics")]
+            Sink::DogStatsD(_) => "dogstatsd",
+            Sink::Meter { .. } => "meter",
+        };
+        f.debug_struct("RequestMetrics")
+            .field("sink", &sink)
+            .finish()
+    }
+}
+
+impl RequestMetrics {
+    /// Sends the request metrics to DogStatsD, the duration being a distribution.
+    #[cfg(feature = "metrics")]
+    pub fn dogstatsd(client: DogStatsD) -> Self {
+        RequestMetrics {
+            sink: Sink::DogStatsD(client),
+        }
+    }
+
+    /// Records the request metrics with instruments created from an OpenTelemetry meter.
+    pub fn meter(meter: &Meter) -> Self {
+        RequestMetrics {
+            sink: Sink::Meter {
+                requests: meter
+                    .u64_counter(REQUESTS_METRIC)
+                    .with_description("Number of HTTP requests")
+                    .init(),
+                errors: meter
+                    .u64_counter(ERRORS_METRIC)
+                    .with_description("Number of failed HTTP requests")
+                    .init(),
+                duration: meter
+                    .f64_histogram(DURATION_METRIC)
+                    .with_description("Duration of HTTP requests")
+                    .with_unit(Unit::new("s"))
+                    .init(),
+            },
+        }
+    }
+
+    /// Records a request. `status` is `None` when the inner service failed.
+    pub(crate) fn record(
+        &self,
+        route: &str,
+        method: &str,
+        status: Option<u16>,
+        duration: Duration,
+    ) {
+        let status_class = match status {
+            Some(status) => format!("{}xx", status / 100),
+            None => "5xx".to_string(),
+        };
+        let is_error = status.is_none_or(|status| status >= 500);
+        let duration = duration.as_secs_f64();
+
+        match &self.sink {
+            #[cfg(feature = "metrics")]
+            Sink::DogStatsD(client) => {
+                let tags = [
+                    format!("http.route:{route}"),
+                    format!("http.request.method:{method}"),
+                    format!("http.status_class:{status_class}"),
+                ];
+                let tags = tags.iter().map(String::as_str).collect::<Vec<_>>();
+                client.count(REQUESTS_METRIC, 1, &tags);
+                if is_error {
+                    client.count(ERRORS_METRIC, 1, &tags);
+                }
+                client.distribution(DURATION_METRIC, duration, &tags);
+            }
+            Sink::Meter {
+                requests,
+                errors,
+                duration: histogram,
+            } => {
+                let attributes = [
+                    KeyValue::new("http.route", route.to_string()),
+                    KeyValue::new("http.request.method", method.to_string()),
+                    KeyValue::new("http.status_class", status_class),
+                ];
+                requests.add(1, &attributes);
+                if is_error {
+                    errors.add(1, &attributes);
+                }
+                histogram.record(duration, &attributes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::axum::OtelAxumLayer;
+    #[cfg(feature = "metrics")]
+    use crate::metrics::tests::{receive, udp_server};
+    #[cfg(feature = "metrics")]
+    use crate::metrics::DogStatsDBuilder;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use http::{Request, Response, StatusCode};
+    use opentelemetry::metrics::MeterProvider as _;
+    use opentelemetry_sdk::metrics::data::{self, ResourceMetrics};
+    use opentelemetry_sdk::metrics::{MeterProvider, PeriodicReader};
+    use opentelemetry_sdk::runtime;
+    use opentelemetry_sdk::testing::metrics::InMemoryMetricsExporter;
+    use opentelemetry_sdk::AttributeSet;
+    use std::convert::Infallible;
+    use tower::{Layer, ServiceExt};
+
+    // records the requests handled by `app` with a meter, and returns the collected metrics
+    async fn meter_metrics<S>(app: impl FnOnce(RequestMetrics) -> S) -> Vec<ResourceMetrics>
+    where
+        S: tower::Service<Request<Body>, Response = Response<Body>> + Clone,
+        S::Error: std::fmt::Debug,
+    {
+        let exporter = InMemoryMetricsExporter::default();
+        let provider = MeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone(), runtime::Tokio).build())
+            .build();
+        let app = app(RequestMetrics::meter(&provider.meter("test")));
+
+        for uri in ["/users/12", "/fail"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+        provider.force_flush().unwrap();
+        exporter.get_finished_metrics().unwrap()
+    }
+
+    fn aggregation<'a, T: 'static>(metrics: &'a [ResourceMetrics], name: &str) -> &'a T {
+        metrics
+            .iter()
+            .flat_map(|resource| &resource.scope_metrics)
+            .flat_map(|scope| &scope.metrics)
+            .find(|metric| metric.name == name)
+            .and_then(|metric| metric.data.as_any().downcast_ref())
+            .unwrap_or_else(|| panic!("no {name} metric"))
+    }
+
+    fn route(attributes: &AttributeSet) -> String {
+        attributes
+            .iter()
+            .find(|(key, _)| key.as_str() == "http.route")
+            .map(|(_, value)| value.to_string())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_meter_request_metrics() {
+        let metrics = meter_metrics(|metrics| {
+            Router::new()
+                .route("/users/{id}", get(|| async { StatusCode::OK }))
+                .route("/fail", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+                .layer(OtelAxumLayer::default().metrics(metrics))
+        })
+        .await;
+
+        let requests = aggregation::<data::Sum<u64>>(&metrics, REQUESTS_METRIC);
+        let mut routes: Vec<_> = requests
+            .data_points
+            .iter()
+            .map(|point| (route(&point.attributes), point.value))
+            .collect();
+        routes.sort();
+        assert_eq!(
+            routes,
+            [("/fail".to_string(), 1), ("/users/{id}".to_string(), 1)]
+        );
+        let errors = aggregation::<data::Sum<u64>>(&metrics, ERRORS_METRIC);
+        assert_eq!(errors.data_points.len(), 1);
+        assert_eq!(route(&errors.data_points[0].attributes), "/fail");
+        let duration = aggregation::<data::Histogram<f64>>(&metrics, DURATION_METRIC);
+        assert_eq!(duration.data_points.len(), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_duration_includes_inner_call() {
+        let metrics = meter_metrics(|metrics| {
+            let inner = tower::service_fn(|_request: Request<Body>| {
+                // work done before returning the response future
+                std::thread::sleep(Duration::from_millis(20));
+                async { Ok::<_, Infallible>(Response::new(Body::empty())) }
+            });
+            OtelAxumLayer::default().metrics(metrics).layer(inner)
+        })
+        .await;
+
+        let duration = aggregation::<data::Histogram<f64>>(&metrics, DURATION_METRIC);
+        let point = &duration.data_points[0];
+        assert_eq!(point.count, 2);
+        assert!(point.sum >= 0.04, "{}", point.sum);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn test_dogstatsd_request_metrics() {
+        let (server, url) = udp_server();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(url)
+            .build()
+            .unwrap();
+        let app = Router::new()
+            .route("/users/{id}", get(|| async { StatusCode::OK }))
+            .route("/fail", get(|| async { StatusCode::SERVICE_UNAVAILABLE }))
+            .layer(OtelAxumLayer::default().metrics(RequestMetrics::dogstatsd(statsd)));
+
+        let request = Request::get("/users/12").body(axum::body::Body::empty());
+        app.clone().oneshot(request.unwrap()).await.unwrap();
+        let request = Request::get("/fail").body(axum::body::Body::empty());
+        app.oneshot(request.unwrap()).await.unwrap();
+
+        let tags = "http.route:/users/{id},http.request.method:GET,http.status_class:2xx,service:my-service";
+        assert_eq!(receive(&server), format!("{REQUESTS_METRIC}:1|c|#{tags}"));
+        assert!(receive(&server).starts_with(&format!("{DURATION_METRIC}:")));
+
+        let tags =
+            "http.route:/fail,http.request.method:GET,http.status_class:5xx,service:my-service";
+        assert_eq!(receive(&server), format!("{REQUESTS_METRIC}:1|c|#{tags}"));
+        assert_eq!(receive(&server), format!("{ERRORS_METRIC}:1|c|#{tags}"));
+        let duration = receive(&server);
+        assert!(duration.starts_with(&format!("{DURATION_METRIC}:")));
+        assert!(duration.ends_with(&format!("|d|#{tags}")));
+    }
+}
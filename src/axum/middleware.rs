@@ -40,12 +40,14 @@ use std::{
     future::Future,
     pin::Pin,
     task::{Context, Poll},
+    time::Instant,
 };
 use tower::{Layer, Service};
 use tracing::Span;
 use tracing_opentelemetry_instrumentation_sdk::http as otel_http;
 
 use crate::axum::http_server;
+use crate::axum::metrics::RequestMetrics;
 
 #[deprecated(
     since = "0.12.0",
@@ -61,12 +63,15 @@ pub type Filter = fn(&str) -> bool;
 /// layer/middleware for axum:
 ///
 /// - propagate `OpenTelemetry` context (`trace_id`,...) to server
+/// - keep the Datadog origin (`x-datadog-origin`) in the trace context, see [`crate::propagator`]
 /// - create a Span for `OpenTelemetry` (and tracing) on call
+/// - optionally record the request rate, errors and duration, see [`OtelAxumLayer::metrics`]
 ///
 /// `OpenTelemetry` context are extracted from tracing's span.
 #[derive(Default, Debug, Clone)]
 pub struct OtelAxumLayer {
     filter: Option<Filter>,
+    metrics: Option<RequestMetrics>,
 }
 
 // add a builder like api
@@ -75,6 +80,16 @@ impl OtelAxumLayer {
     pub fn filter(self, filter: Filter) -> Self {
         OtelAxumLayer {
             filter: Some(filter),
+            ..self
+        }
+    }
+
+    /// Records the RED metrics of the requests kept by the filter, see [`crate::axum::metrics`].
+    #[must_use]
+    pub fn metrics(self, metrics: RequestMetrics) -> Self {
+        OtelAxumLayer {
+            metrics: Some(metrics),
+            ..self
         }
     }
 }
@@ -86,6 +101,7 @@ impl<S> Layer<S> for OtelAxumLayer {
         OtelAxumService {
             inner,
             filter: self.filter,
+            metrics: self.metrics.clone(),
         }
     }
 }
@@ -94,6 +110,7 @@ impl<S> Layer<S> for OtelAxumLayer {
 pub struct OtelAxumService<S> {
     inner: S,
     filter: Option<Filter>,
+    metrics: Option<RequestMetrics>,
 }
 
 impl<S, B, B2> Service<Request<B>> for OtelAxumService<S>
@@ -116,7 +133,8 @@ where
     fn call(&mut self, req: Request<B>) -> Self::Future {
         use tracing_opentelemetry::OpenTelemetrySpanExt;
         let req = req;
-        let span = if self.filter.map_or(true, |f| f(req.uri().path())) {
+        let mut metrics = None;
+        let span = if self.filter.is_none_or(|f| f(req.uri().path())) {
             let span = http_server::make_span_from_request(&req);
 
             let route = http_route(&req);
@@ -126,10 +144,17 @@ where
             span.record("otel.name", format!("{method} {route}").trim());
 
             span.set_parent(otel_http::extract_context(req.headers()));
+            metrics = self.metrics.clone().map(|metrics| RequestMetricsContext {
+                metrics,
+                route: route.to_string(),
+                method: method.into_owned(),
+            });
             span
         } else {
             tracing::Span::none()
         };
+        // the synchronous part of the inner call is part of the request duration
+        let start = Instant::now();
         let future = {
             let _ = span.enter();
             self.inner.call(req)
@@ -137,6 +162,8 @@ where
         ResponseFuture {
             inner: future,
             span,
+            metrics,
+            start,
         }
     }
 }
@@ -149,10 +176,18 @@ pin_project! {
         #[pin]
         pub(crate) inner: F,
         pub(crate) span: Span,
-        // pub(crate) start: Instant,
+        pub(crate) metrics: Option<RequestMetricsContext>,
+        pub(crate) start: Instant,
     }
 }
 
+/// Route and method of the request, recorded with its metrics once the response is ready.
+pub(crate) struct RequestMetricsContext {
+    metrics: RequestMetrics,
+    route: String,
+    method: String,
+}
+
 impl<Fut, ResBody, E> Future for ResponseFuture<Fut>
 where
     Fut: Future<Output = Result<Response<ResBody>, E>>,
@@ -165,6 +200,18 @@ where
         let _guard = this.span.enter();
         let result = futures_util::ready!(this.inner.poll(cx));
         http_server::update_span_from_response_or_error(this.span, &result);
+        if let Some(context) = this.metrics.take() {
+            let status = result
+                .as_ref()
+                .ok()
+                .map(|response| response.status().as_u16());
+            context.metrics.record(
+                &context.route,
+                &context.method,
+                status,
+                this.start.elapsed(),
+            );
+        }
 
         Poll::Ready(result)
     }
@@ -5,6 +5,8 @@
 //!
 //! Also, exposes OtelAxumLayer from the same project, but hacked to support datadog.
 //!
+//! OtelAxumLayer can also record RED metrics of the requests, see [`metrics`].
+//!
 //! Additionally, a shutdown helper function named `shutdown_signal` is also exposed
 
 mod shutdown;
@@ -17,3 +19,6 @@ pub use middleware::*;
 pub use axum_tracing_opentelemetry::middleware::OtelInResponseLayer;
 
 mod http_server;
+
+pub mod metrics;
+pub use metrics::RequestMetrics;
@@ -0,0 +1,396 @@
+//! Agentless trace exporter.
+//!
+//! Sends spans directly to the Datadog trace intake (`https://trace.agent.<DD_SITE>/api/v0.2/traces`),
+//! authenticated with `DD_API_KEY`, for deployments without a Datadog agent (e.g. batch jobs on Fargate).
+//!
+//! Payloads are encoded with the intake protobuf format, gzip compressed, split to stay under
+//! a maximum size and retried with an exponential backoff on network errors, `408`, `429` and
+//! `5xx` responses. Retries stop before the export timeout of the batch span processor
+//! (`OTEL_BSP_EXPORT_TIMEOUT`), so that the spans of the payloads not sent are counted as dropped.
+use std::fmt::{Debug, Formatter};
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use opentelemetry::global;
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use prost::Message;
+
+use super::model::{DatadogSpan, ModelConfig};
+use super::pb::{AgentPayload, TraceChunk, TracerPayload};
+use super::{group_into_traces, Error};
+use crate::http::{gzip, post_gzip, PostError};
+use crate::mapping::Mapping;
+use crate::processor::runtime_id;
+use crate::propagator::DATADOG_ORIGIN_TAG;
+use crate::telemetry;
+
+const DATADOG_API_KEY_HEADER: &str = "DD-Api-Key";
+const DATADOG_REPORTED_LANGUAGES_HEADER: &str = "X-Datadog-Reported-Languages";
+
+/// Default maximum size of an uncompressed payload.
+pub const DEFAULT_MAX_PAYLOAD_SIZE: usize = 2 * 1024 * 1024;
+/// Default number of retries of a failed request.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+/// Default time allowed to export a batch, the default export timeout of the batch span processor.
+pub const DEFAULT_EXPORT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Returns the trace intake URL of a Datadog site (e.g. `datadoghq.eu`).
+pub fn intake_url(site: &str) -> String {
+    format!("https://trace.agent.{site}/api/v0.2/traces")
+}
+
+/// Datadog span exporter sending spans to the Datadog intake, without an agent.
+pub struct AgentlessExporter {
+    client: reqwest::Client,
+    intake_url: String,
+    api_key: String,
+    max_payload_size: usize,
+    max_retries: u32,
+    export_timeout: Duration,
+    model_config: ModelConfig,
+    mapping: Mapping,
+}
+
+impl Debug for AgentlessExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentlessExporter")
+            .field("intake_url", &self.intake_url)
+            .field("max_payload_size", &self.max_payload_size)
+            .field("max_retries", &self.max_retries)
+            .field("export_timeout", &self.export_timeout)
+            .field("model_config", &self.model_config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AgentlessExporter {
+    pub(crate) fn new(
+        client: reqwest::Client,
+        intake_url: String,
+        api_key: String,
+        model_config: ModelConfig,
+        mapping: Mapping,
+    ) -> Self {
+        AgentlessExporter {
+            client,
+            intake_url,
+            api_key,
+            max_payload_size: DEFAULT_MAX_PAYLOAD_SIZE,
+            max_retries: DEFAULT_MAX_RETRIES,
+            export_timeout: DEFAULT_EXPORT_TIMEOUT,
+            model_config,
+            mapping,
+        }
+    }
+
+    /// Sets the export timeout of the batch span processor, which the export must not exceed.
+    pub(crate) fn with_export_timeout(mut self, export_timeout: Duration) -> Self {
+        self.export_timeout = export_timeout;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_max_payload_size(mut self, max_payload_size: usize) -> Self {
+        self.max_payload_size = max_payload_size;
+        self
+    }
+
+    #[cfg(test)]
+    fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    fn build_chunks(&self, batch: &[SpanData]) -> Vec<TraceChunk> {
+        group_into_traces(batch)
+            .into_iter()
+            .map(|trace| {
+                let spans = trace
+                    .into_iter()
+                    .map(|span| DatadogSpan::new(span, &self.model_config, &self.mapping))
+                    .collect::<Vec<_>>();
+                let sampled = spans.iter().any(DatadogSpan::is_sampled);
+                let origin = spans
+                    .iter()
+                    .find_map(|span| span.meta_value(DATADOG_ORIGIN_TAG))
+                    .unwrap_or_default()
+                    .to_string();
+                TraceChunk {
+                    priority: i32::from(sampled),
+                    origin,
+                    spans: spans.iter().map(Into::into).collect(),
+                    ..Default::default()
+                }
+            })
+            .collect()
+    }
+
+    fn tracer_payload(&self, chunks: Vec<TraceChunk>) -> TracerPayload {
+        TracerPayload {
+            language_name: "rust".to_string(),
+            tracer_version: env!("CARGO_PKG_VERSION").to_string(),
+            runtime_id: runtime_id().to_string(),
+            chunks,
+            env: self.model_config.env.clone().unwrap_or_default(),
+            app_version: self.model_config.version.clone().unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    // splits the chunks in payloads smaller than `max_payload_size`, dropping (and reporting)
+    // traces that don't fit in a payload on their own. Payloads are returned with their
+    // number of spans.
+    fn build_payloads(&self, batch: &[SpanData]) -> Result<Vec<(Vec<u8>, usize)>, Error> {
+        let mut payloads = Vec::new();
+        let mut chunks = Vec::new();
+        let mut size = 0;
+        for chunk in self.build_chunks(batch) {
+            let chunk_size = chunk.encoded_len();
+            if chunk_size > self.max_payload_size {
+                telemetry::spans_dropped(chunk.spans.len());
+                global::handle_error(TraceError::from(Error::PayloadTooLarge(chunk_size)));
+                continue;
+            }
+            if size + chunk_size > self.max_payload_size && !chunks.is_empty() {
+                payloads.push(self.encode(std::mem::take(&mut chunks))?);
+                size = 0;
+            }
+            size += chunk_size;
+            chunks.push(chunk);
+        }
+        if !chunks.is_empty() {
+            payloads.push(self.encode(chunks)?);
+        }
+        Ok(payloads)
+    }
+
+    fn encode(&self, chunks: Vec<TraceChunk>) -> Result<(Vec<u8>, usize), Error> {
+        let span_count = chunks.iter().map(|chunk| chunk.spans.len()).sum();
+        let payload = AgentPayload {
+            env: self.model_config.env.clone().unwrap_or_default(),
+            tracer_payloads: vec![self.tracer_payload(chunks)],
+            ..Default::default()
+        };
+
+        gzip(&[&payload.encode_to_vec()])
+            .map(|payload| (payload, span_count))
+            .map_err(|err| Error::Compression(err.to_string()))
+    }
+}
+
+// sends a payload, retrying until `deadline`
+async fn send_payload(
+    client: reqwest::Client,
+    intake_url: String,
+    api_key: String,
+    payload: Vec<u8>,
+    max_retries: u32,
+    deadline: Instant,
+) -> Result<(), Error> {
+    let request = || {
+        client
+            .post(&intake_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+            .header(DATADOG_API_KEY_HEADER, &api_key)
+            .header(DATADOG_REPORTED_LANGUAGES_HEADER, "rust")
+    };
+    post_gzip(request, &payload, max_retries, deadline, |status| {
+        telemetry::export_attempt();
+        telemetry::export_bytes(payload.len());
+        if let Some(status) = status {
+            telemetry::http_status_code(status.as_u16());
+        }
+    })
+    .await
+    .map_err(|err| match err {
+        PostError::Request(err) => err.into(),
+        PostError::Timeout => Error::Timeout,
+    })
+}
+
+impl SpanExporter for AgentlessExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let payloads = match self.build_payloads(&batch) {
+            Ok(payloads) => payloads,
+            Err(err) => {
+                telemetry::export_error();
+                telemetry::spans_dropped(batch.len());
+                return Box::pin(std::future::ready(Err(err.into())));
+            }
+        };
+
+        let client = self.client.clone();
+        let intake_url = self.intake_url.clone();
+        let api_key = self.api_key.clone();
+        let max_retries = self.max_retries;
+        // finishes before the batch span processor drops the export
+        let deadline = Instant::now() + self.export_timeout - self.export_timeout / 10;
+        Box::pin(async move {
+            // every payload is sent, returning the first error
+            let mut first_error = None;
+            for (payload, span_count) in payloads {
+                let result = send_payload(
+                    client.clone(),
+                    intake_url.clone(),
+                    api_key.clone(),
+                    payload,
+                    max_retries,
+                    deadline,
+                )
+                .await;
+                if let Err(err) = result {
+                    telemetry::export_error();
+                    telemetry::spans_dropped(span_count);
+                    first_error.get_or_insert(err);
+                }
+            }
+            first_error.map_or(Ok(()), |err| Err(err.into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::span_with_ids;
+    use crate::http::mock::{self, Requests};
+    use hyper::StatusCode;
+
+    fn mock_intake(statuses: Vec<StatusCode>) -> (String, Requests) {
+        mock::mock_intake("/api/v0.2/traces", statuses)
+    }
+
+    fn payload(requests: &Requests, index: usize) -> AgentPayload {
+        AgentPayload::decode(requests.lock().unwrap()[index].body.as_slice()).unwrap()
+    }
+
+    fn exporter(url: String) -> AgentlessExporter {
+        AgentlessExporter::new(
+            reqwest::Client::new(),
+            url,
+            "my-api-key".to_string(),
+            ModelConfig {
+                service: "my-service".to_string(),
+                env: Some("test".to_string()),
+                ..Default::default()
+            },
+            Mapping::default(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_export_to_intake() {
+        let (url, requests) = mock_intake(vec![]);
+
+        exporter(url)
+            .export(vec![span_with_ids(1, 2, 0), span_with_ids(1, 3, 0)])
+            .await
+            .unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        let payload = payload(&requests, 0);
+        let request = requests.lock().unwrap()[0].clone();
+        assert_eq!(request.uri.path(), "/api/v0.2/traces");
+        let headers = &request.headers;
+        assert_eq!(headers[DATADOG_API_KEY_HEADER], "my-api-key");
+        assert_eq!(headers["content-encoding"], "gzip");
+        assert_eq!(headers["content-type"], "application/x-protobuf");
+
+        let tracer_payload = &payload.tracer_payloads[0];
+        assert_eq!(tracer_payload.language_name, "rust");
+        assert_eq!(tracer_payload.env, "test");
+        assert_eq!(tracer_payload.chunks.len(), 1);
+        let chunk = &tracer_payload.chunks[0];
+        assert_eq!(chunk.priority, 1);
+        assert_eq!(chunk.spans.len(), 2);
+        assert_eq!(chunk.spans[0].service, "my-service");
+        assert_eq!(chunk.spans[0].resource, "do_something");
+        assert_eq!(chunk.spans[0].trace_id, 1);
+        assert_eq!(chunk.spans[1].span_id, 3);
+    }
+
+    #[tokio::test]
+    async fn test_export_retries_server_errors() {
+        let (url, requests) = mock_intake(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::TOO_MANY_REQUESTS,
+        ]);
+
+        exporter(url)
+            .export(vec![span_with_ids(1, 2, 0)])
+            .await
+            .unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_export_does_not_retry_client_errors() {
+        let (url, requests) = mock_intake(vec![StatusCode::FORBIDDEN]);
+
+        let result = exporter(url)
+            .with_max_retries(5)
+            .export(vec![span_with_ids(1, 2, 0)])
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_export_splits_payloads() {
+        let (url, requests) = mock_intake(vec![]);
+        let max_payload_size =
+            exporter(String::new()).build_chunks(&[span_with_ids(1, 2, 0)])[0].encoded_len() + 1;
+
+        exporter(url)
+            .with_max_payload_size(max_payload_size)
+            .export(vec![span_with_ids(1, 2, 0), span_with_ids(2, 3, 0)])
+            .await
+            .unwrap();
+
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        for (index, trace_id) in [(0, 1), (1, 2)] {
+            let payload = payload(&requests, index);
+            assert_eq!(
+                payload.tracer_payloads[0].chunks[0].spans[0].trace_id,
+                trace_id
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_sends_payloads_after_a_failure() {
+        let (url, requests) = mock_intake(vec![StatusCode::BAD_REQUEST]);
+        let max_payload_size =
+            exporter(String::new()).build_chunks(&[span_with_ids(1, 2, 0)])[0].encoded_len() + 1;
+        let before = telemetry::tracer_stats();
+
+        let result = exporter(url)
+            .with_max_payload_size(max_payload_size)
+            .export(vec![span_with_ids(1, 2, 0), span_with_ids(2, 3, 0)])
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+        assert!(telemetry::tracer_stats().spans_dropped > before.spans_dropped);
+    }
+
+    #[tokio::test]
+    async fn test_export_retries_within_the_export_timeout() {
+        let (url, requests) = mock_intake(vec![StatusCode::SERVICE_UNAVAILABLE; 10]);
+
+        let start = Instant::now();
+        let result = exporter(url)
+            .with_max_retries(10)
+            .with_export_timeout(Duration::from_millis(300))
+            .export(vec![span_with_ids(1, 2, 0)])
+            .await;
+
+        assert!(result.is_err());
+        assert!(start.elapsed() < Duration::from_millis(300));
+        // the second backoff would end after the deadline
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+}
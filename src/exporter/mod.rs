@@ -0,0 +1,430 @@
+//! Datadog trace exporter.
+//!
+//! This module contains a [`SpanExporter`] sending spans to the Datadog agent,
+//! encoded in the agent's v0.4 or v0.5 msgpack formats.
+//!
+//! Unlike the exporter provided by [`opentelemetry_datadog`], it has full control over the
+//! meta and metrics fields sent to Datadog (e.g. `_sampling_priority_v1`, `_dd.measured` and
+//! `_top_level`), so the OpenTelemetry stack can be upgraded independently.
+//!
+//! APM stats can also be computed by the tracer, see
+//! [`crate::tracer::TracerBuilder::with_stats_computation`].
+//!
+//! [`opentelemetry_datadog`]: https://github.com/open-telemetry/opentelemetry-rust-contrib/tree/main/opentelemetry-datadog
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+use futures_util::future::BoxFuture;
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::export::ExportError;
+
+use crate::agent::{AgentDiscovery, SharedFeatures};
+use crate::mapping::Mapping;
+use crate::telemetry;
+
+#[cfg(feature = "agentless")]
+pub mod agentless;
+mod model;
+#[cfg(feature = "agentless")]
+mod pb;
+pub(crate) mod stats;
+mod v04;
+mod v05;
+
+pub(crate) use model::DatadogSpan;
+pub(crate) use model::ModelConfig;
+
+/// Header name used to inform the Datadog agent of the number of traces in the payload
+const DATADOG_TRACE_COUNT_HEADER: &str = "X-Datadog-Trace-Count";
+/// Header names used to inform the Datadog agent of the tracer language and version
+const DATADOG_META_LANG_HEADER: &str = "Datadog-Meta-Lang";
+const DATADOG_META_TRACER_VERSION_HEADER: &str = "Datadog-Meta-Tracer-Version";
+/// Header names used to inform the Datadog agent of the traces dropped by the tracer
+const DATADOG_DROPPED_P0_TRACES_HEADER: &str = "Datadog-Client-Dropped-P0-Traces";
+const DATADOG_DROPPED_P0_SPANS_HEADER: &str = "Datadog-Client-Dropped-P0-Spans";
+
+/// Version of the Datadog agent trace intake API.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ApiVersion {
+    /// Version 0.4
+    Version04,
+    /// Version 0.5 - requires datadog-agent v7.22.0 or above
+    #[default]
+    Version05,
+}
+
+impl ApiVersion {
+    pub fn path(self) -> &'static str {
+        match self {
+            ApiVersion::Version04 => "/v0.4/traces",
+            ApiVersion::Version05 => "/v0.5/traces",
+        }
+    }
+
+    /// Encodes the traces, with native span events if supported by the agent (v0.4 only).
+    fn encode(self, traces: &[Vec<DatadogSpan>], span_events: bool) -> Result<Vec<u8>, Error> {
+        match self {
+            ApiVersion::Version04 => v04::encode(traces, span_events),
+            ApiVersion::Version05 => v05::encode(traces),
+        }
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches('v') {
+            "0.4" => Ok(ApiVersion::Version04),
+            "0.5" => Ok(ApiVersion::Version05),
+            _ => Err(Error::Config(format!("unsupported api version {s}"))),
+        }
+    }
+}
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiVersion::Version04 => write!(f, "v0.4"),
+            ApiVersion::Version05 => write!(f, "v0.5"),
+        }
+    }
+}
+
+/// Errors of the Datadog exporter.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Spans could not be encoded
+    MessagePack,
+    /// The request to the Datadog agent or intake failed
+    Request(reqwest::Error),
+    /// Invalid exporter configuration
+    Config(String),
+    /// Payload could not be compressed
+    Compression(String),
+    /// A trace is larger than the maximum payload size
+    PayloadTooLarge(usize),
+    /// The agent answered with an unexpected response
+    InvalidResponse(String),
+    /// The export didn't complete within the export timeout
+    Timeout,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::MessagePack => write!(f, "message pack error"),
+            Error::Request(err) => write!(f, "request to datadog failed: {err}"),
+            Error::Config(message) => write!(f, "invalid configuration: {message}"),
+            Error::Compression(message) => write!(f, "compression error: {message}"),
+            Error::PayloadTooLarge(size) => {
+                write!(f, "trace of {size} bytes exceeds the maximum payload size")
+            }
+            Error::InvalidResponse(message) => write!(f, "invalid agent response: {message}"),
+            Error::Timeout => write!(f, "export timed out"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl ExportError for Error {
+    fn exporter_name(&self) -> &'static str {
+        "datadog"
+    }
+}
+
+impl From<rmp::encode::ValueWriteError> for Error {
+    fn from(_: rmp::encode::ValueWriteError) -> Self {
+        Self::MessagePack
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Request(err)
+    }
+}
+
+/// Datadog span exporter
+#[derive(Clone)]
+pub struct DatadogExporter {
+    client: reqwest::Client,
+    agent_endpoint: String,
+    features: SharedFeatures,
+    discovery: Option<AgentDiscovery>,
+    model_config: ModelConfig,
+    mapping: Mapping,
+}
+
+impl Debug for DatadogExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DatadogExporter")
+            .field("agent_endpoint", &self.agent_endpoint)
+            .field("features", &self.features)
+            .field("model_config", &self.model_config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DatadogExporter {
+    /// Creates the exporter, whose API version and client computed stats are read from the
+    /// shared features on every export, see [`crate::agent`].
+    pub(crate) fn new(
+        client: reqwest::Client,
+        agent_endpoint: &str,
+        features: SharedFeatures,
+        model_config: ModelConfig,
+        mapping: Mapping,
+    ) -> Self {
+        DatadogExporter {
+            client,
+            agent_endpoint: agent_endpoint.trim_end_matches('/').to_string(),
+            features,
+            discovery: None,
+            model_config,
+            mapping,
+        }
+    }
+
+    /// Waits for the discovery of the agent features before the first export.
+    pub(crate) fn with_discovery(mut self, discovery: AgentDiscovery) -> Self {
+        self.discovery = Some(discovery);
+        self
+    }
+
+    fn send(&self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        match self.build_request(&batch) {
+            Ok(request) => Box::pin(send_request(request, batch.len())),
+            Err(err) => {
+                telemetry::export_error();
+                telemetry::spans_dropped(batch.len());
+                Box::pin(std::future::ready(Err(err.into())))
+            }
+        }
+    }
+
+    fn build_request(&self, batch: &[SpanData]) -> Result<reqwest::RequestBuilder, Error> {
+        let mut traces = group_into_traces(batch)
+            .into_iter()
+            .map(|trace| {
+                trace
+                    .into_iter()
+                    .map(|span| DatadogSpan::new(span, &self.model_config, &self.mapping))
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+
+        let features = *self.features.read().unwrap();
+        let (mut dropped_traces, mut dropped_spans) = (0, 0);
+        if features.client_drop_p0s {
+            traces.retain(|trace| {
+                let sampled = trace.iter().any(DatadogSpan::is_sampled);
+                if !sampled {
+                    dropped_traces += 1;
+                    dropped_spans += trace.len();
+                }
+                sampled
+            });
+        }
+        let data = features.api_version.encode(&traces, features.span_events)?;
+        telemetry::export_attempt();
+        telemetry::export_bytes(data.len());
+
+        let mut request = self
+            .client
+            .post(format!(
+                "{}{}",
+                self.agent_endpoint,
+                features.api_version.path()
+            ))
+            .header(reqwest::header::CONTENT_TYPE, "application/msgpack")
+            .header(DATADOG_TRACE_COUNT_HEADER, traces.len())
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            );
+        if features.client_computed_stats {
+            request = request.header(stats::CLIENT_COMPUTED_STATS_HEADER, "yes");
+        }
+        if features.client_drop_p0s {
+            request = request
+                .header(DATADOG_DROPPED_P0_TRACES_HEADER, dropped_traces)
+                .header(DATADOG_DROPPED_P0_SPANS_HEADER, dropped_spans);
+        }
+        Ok(request.body(data))
+    }
+}
+
+pub(crate) fn group_into_traces(spans: &[SpanData]) -> Vec<Vec<&SpanData>> {
+    let mut indexes = HashMap::new();
+    let mut traces: Vec<Vec<&SpanData>> = Vec::new();
+    for span in spans {
+        let index = *indexes
+            .entry(span.span_context.trace_id())
+            .or_insert_with(|| {
+                traces.push(Vec::new());
+                traces.len() - 1
+            });
+        traces[index].push(span);
+    }
+    traces
+}
+
+async fn send_request(request: reqwest::RequestBuilder, span_count: usize) -> ExportResult {
+    let result = request.send().await.and_then(|response| {
+        telemetry::http_status_code(response.status().as_u16());
+        response.error_for_status()
+    });
+    if let Err(err) = result {
+        telemetry::export_error();
+        telemetry::spans_dropped(span_count);
+        return Err(TraceError::from(Error::from(err)));
+    }
+    Ok(())
+}
+
+impl SpanExporter for DatadogExporter {
+    /// Export spans to datadog-agent
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        if let Some(discovery) = self.discovery.clone() {
+            // the request is built once the API version is known
+            if discovery.peek().is_none() {
+                let exporter = self.clone();
+                return Box::pin(async move {
+                    let _ = discovery.await;
+                    exporter.send(batch).await
+                });
+            }
+            self.discovery = None;
+        }
+        self.send(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::span_with_ids;
+    use rmpv::Value;
+
+    fn encode(api_version: ApiVersion, span_events: bool, batch: &[SpanData]) -> Value {
+        let config = ModelConfig {
+            service: "my-service".to_string(),
+            ..Default::default()
+        };
+        let mapping = Mapping::default();
+        let traces = group_into_traces(batch)
+            .into_iter()
+            .map(|trace| {
+                trace
+                    .into_iter()
+                    .map(|span| DatadogSpan::new(span, &config, &mapping))
+                    .collect()
+            })
+            .collect::<Vec<Vec<_>>>();
+        let data = api_version.encode(&traces, span_events).unwrap();
+        rmpv::decode::read_value(&mut data.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn test_group_into_traces() {
+        let batch = vec![
+            span_with_ids(1, 1, 0),
+            span_with_ids(2, 2, 0),
+            span_with_ids(1, 3, 0),
+        ];
+
+        let traces = group_into_traces(&batch);
+
+        assert_eq!(traces.len(), 2);
+        assert_eq!(traces[0], vec![&batch[0], &batch[2]]);
+        assert_eq!(traces[1], vec![&batch[1]]);
+    }
+
+    #[test]
+    fn test_encode_v04() {
+        let payload = encode(ApiVersion::Version04, false, &[span_with_ids(1, 2, 0)]);
+
+        let span = &payload[0][0];
+        let field = |name: &str| {
+            span.as_map()
+                .unwrap()
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(name))
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(field("service").as_str(), Some("my-service"));
+        assert_eq!(field("name").as_str(), Some("opentelemetry-datadog"));
+        assert_eq!(field("resource").as_str(), Some("do_something"));
+        assert_eq!(field("trace_id").as_u64(), Some(1));
+        assert_eq!(field("span_id").as_u64(), Some(2));
+        assert_eq!(field("type").as_str(), Some(""));
+        assert!(field("metrics")
+            .as_map()
+            .unwrap()
+            .iter()
+            .any(|(k, v)| k.as_str() == Some("_sampling_priority_v1") && v.as_f64() == Some(1.0)));
+    }
+
+    #[test]
+    fn test_encode_v04_span_events() {
+        let mut span = span_with_ids(1, 2, 0);
+        span.events = opentelemetry_sdk::trace::EvictedQueue::new(1);
+        span.events
+            .append_vec(&mut vec![opentelemetry::trace::Event::with_name("retry")]);
+        let field = |payload: &Value, name: &str| {
+            payload[0][0]
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(name))
+                .map(|(_, v)| v.clone())
+        };
+        let has_events_meta = |payload: &Value| {
+            field(payload, "meta")
+                .unwrap()
+                .as_map()
+                .unwrap()
+                .iter()
+                .any(|(k, _)| k.as_str() == Some("events"))
+        };
+
+        let payload = encode(ApiVersion::Version04, true, &[span.clone()]);
+        let events = field(&payload, "span_events").unwrap();
+        assert_eq!(events[0]["name"].as_str(), Some("retry"));
+        assert!(!has_events_meta(&payload));
+
+        let payload = encode(ApiVersion::Version04, false, &[span]);
+        assert_eq!(field(&payload, "span_events"), None);
+        assert!(has_events_meta(&payload));
+    }
+
+    #[test]
+    fn test_encode_v05() {
+        let payload = encode(ApiVersion::Version05, false, &[span_with_ids(1, 2, 0)]);
+
+        let dictionary = payload[0].as_array().unwrap();
+        let string = |value: &Value| dictionary[value.as_u64().unwrap() as usize].clone();
+        let span = &payload[1][0][0];
+        assert_eq!(span.as_array().unwrap().len(), 12);
+        assert_eq!(string(&span[0]).as_str(), Some("my-service"));
+        assert_eq!(string(&span[1]).as_str(), Some("opentelemetry-datadog"));
+        assert_eq!(string(&span[2]).as_str(), Some("do_something"));
+        assert_eq!(span[3].as_u64(), Some(1));
+        assert_eq!(span[4].as_u64(), Some(2));
+        assert_eq!(string(&span[11]).as_str(), Some(""));
+    }
+
+    #[test]
+    fn test_api_version_from_str() {
+        assert_eq!("v0.4".parse::<ApiVersion>().unwrap(), ApiVersion::Version04);
+        assert_eq!("0.5".parse::<ApiVersion>().unwrap(), ApiVersion::Version05);
+        assert!("v0.3".parse::<ApiVersion>().is_err());
+    }
+}
@@ -0,0 +1,319 @@
+//! Datadog span model.
+//!
+//! Spans are converted into this model before being encoded, so that the meta and metrics
+//! fields are controlled in a single place regardless of the API version.
+use std::borrow::Cow;
+use std::time::SystemTime;
+
+use opentelemetry::trace::{Event, SpanKind, Status};
+use opentelemetry::{Array, Value};
+use opentelemetry_sdk::export::trace::SpanData;
+
+use crate::mapping::{Mapping, OPERATION_NAME_KEY, RESOURCE_NAME_KEY, SPAN_TYPE_KEY};
+
+/// Metric holding the sampling decision of the trace.
+pub(crate) const SAMPLING_PRIORITY_KEY: &str = "_sampling_priority_v1";
+/// Metric flagging spans for which Datadog computes trace metrics.
+pub(crate) const MEASURED_KEY: &str = "_dd.measured";
+/// Meta holding the span events as JSON, for agents without native span events support.
+pub(crate) const EVENTS_KEY: &str = "events";
+
+// attributes only used to compute the span fields, which are not sent as meta
+const MAPPING_KEYS: [&str; 3] = [OPERATION_NAME_KEY, RESOURCE_NAME_KEY, SPAN_TYPE_KEY];
+
+// numeric attributes Datadog expects as meta
+const NUMERIC_META_KEYS: [&str; 2] = ["http.status_code", "http.response.status_code"];
+
+/// Unified service tags added to every span.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModelConfig {
+    pub(crate) service: String,
+    pub(crate) env: Option<String>,
+    pub(crate) version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DatadogSpan<'a> {
+    pub(crate) service: &'a str,
+    pub(crate) name: Cow<'a, str>,
+    pub(crate) resource: Cow<'a, str>,
+    pub(crate) trace_id: u64,
+    pub(crate) span_id: u64,
+    pub(crate) parent_id: u64,
+    pub(crate) start: i64,
+    pub(crate) duration: i64,
+    pub(crate) error: i32,
+    pub(crate) meta: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    pub(crate) metrics: Vec<(Cow<'a, str>, f64)>,
+    pub(crate) r#type: &'a str,
+    pub(crate) events: Vec<&'a Event>,
+}
+
+impl<'a> DatadogSpan<'a> {
+    pub(crate) fn new(span: &'a SpanData, config: &'a ModelConfig, mapping: &Mapping) -> Self {
+        // Safe until the year 2262 when Datadog will need to change their API
+        let start = span
+            .start_time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|x| x.as_nanos() as i64)
+            .unwrap_or(0);
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .map(|x| x.as_nanos() as i64)
+            .unwrap_or(0);
+
+        let mut meta: Vec<(Cow<'a, str>, Cow<'a, str>)> = Vec::new();
+        let mut metrics: Vec<(Cow<'a, str>, f64)> = Vec::new();
+
+        for (key, value) in span.resource.iter() {
+            if key.as_str() != "service.name" {
+                meta.push((Cow::Borrowed(key.as_str()), value.as_str()));
+            }
+        }
+        if let Some(env) = &config.env {
+            meta.push((Cow::Borrowed("env"), Cow::Borrowed(env)));
+        }
+        if let Some(version) = &config.version {
+            meta.push((Cow::Borrowed("version"), Cow::Borrowed(version)));
+        }
+
+        for kv in span.attributes.iter() {
+            let key = kv.key.as_str();
+            if MAPPING_KEYS.contains(&key) {
+                continue;
+            }
+            match &kv.value {
+                Value::I64(value) if !NUMERIC_META_KEYS.contains(&key) => {
+                    metrics.push((Cow::Borrowed(key), *value as f64))
+                }
+                Value::F64(value) => metrics.push((Cow::Borrowed(key), *value)),
+                value => meta.push((Cow::Borrowed(key), value.as_str())),
+            }
+        }
+
+        let error = match &span.status {
+            Status::Error { description } => {
+                if !description.is_empty() {
+                    meta.push((Cow::Borrowed("error.message"), description.clone()));
+                } else if let Some(message) = crate::mapping::attribute(span, "exception.message") {
+                    meta.push((Cow::Borrowed("error.message"), Cow::Borrowed(message)));
+                }
+                1
+            }
+            _ => 0,
+        };
+
+        let span_kind = match span.span_kind {
+            SpanKind::Server => "server",
+            SpanKind::Client => "client",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+            SpanKind::Internal => "internal",
+        };
+        meta.push((Cow::Borrowed("span.kind"), Cow::Borrowed(span_kind)));
+
+        let events: Vec<&Event> = span.events.iter().collect();
+        if !events.is_empty() {
+            meta.push((Cow::Borrowed(EVENTS_KEY), Cow::Owned(events_json(&events))));
+        }
+
+        metrics.push((
+            Cow::Borrowed(SAMPLING_PRIORITY_KEY),
+            if span.span_context.is_sampled() {
+                1.0
+            } else {
+                0.0
+            },
+        ));
+        if is_measured_kind(&span.span_kind) {
+            metrics.push((Cow::Borrowed(MEASURED_KEY), 1.0));
+        }
+
+        DatadogSpan {
+            service: &config.service,
+            name: mapping.name(span),
+            resource: mapping.resource(span),
+            trace_id: u128::from_be_bytes(span.span_context.trace_id().to_bytes()) as u64,
+            span_id: u64::from_be_bytes(span.span_context.span_id().to_bytes()),
+            parent_id: u64::from_be_bytes(span.parent_span_id.to_bytes()),
+            start,
+            duration,
+            error,
+            meta,
+            metrics,
+            r#type: mapping.span_type(span).unwrap_or_default(),
+            events,
+        }
+    }
+
+    #[cfg(any(feature = "agentless", test))]
+    pub(crate) fn meta_value(&self, key: &str) -> Option<&str> {
+        self.meta
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_ref())
+    }
+
+    pub(crate) fn metric_value(&self, key: &str) -> Option<f64> {
+        self.metrics.iter().find(|(k, _)| k == key).map(|(_, v)| *v)
+    }
+
+    /// Whether the trace of the span is kept, according to its sampling priority.
+    pub(crate) fn is_sampled(&self) -> bool {
+        self.metric_value(SAMPLING_PRIORITY_KEY)
+            .is_some_and(|priority| priority > 0.0)
+    }
+}
+
+/// Whether spans of this kind are measured: those crossing a process boundary, as the official
+/// tracers do.
+pub(crate) fn is_measured_kind(kind: &SpanKind) -> bool {
+    matches!(
+        kind,
+        SpanKind::Client | SpanKind::Producer | SpanKind::Consumer
+    )
+}
+
+pub(crate) fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+// the span events format of the `events` meta, understood by every agent version
+fn events_json(events: &[&Event]) -> String {
+    let events = events
+        .iter()
+        .map(|event| {
+            let attributes = event
+                .attributes
+                .iter()
+                .map(|kv| (kv.key.to_string(), json_value(&kv.value)))
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::json!({
+                "name": event.name,
+                "time_unix_nano": unix_nanos(event.timestamp),
+                "attributes": attributes,
+            })
+        })
+        .collect();
+    serde_json::Value::Array(events).to_string()
+}
+
+fn json_value(value: &Value) -> serde_json::Value {
+    match value {
+        Value::Bool(value) => (*value).into(),
+        Value::I64(value) => (*value).into(),
+        Value::F64(value) => (*value).into(),
+        Value::String(value) => value.as_str().into(),
+        Value::Array(Array::Bool(values)) => values.clone().into(),
+        Value::Array(Array::I64(values)) => values.clone().into(),
+        Value::Array(Array::F64(values)) => values.clone().into(),
+        Value::Array(Array::String(values)) => values
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::span;
+    use crate::processor::TOP_LEVEL_TAG;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::trace::EvictedQueue;
+
+    fn config() -> ModelConfig {
+        ModelConfig {
+            service: "my-service".to_string(),
+            env: Some("test".to_string()),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn test_span_fields() {
+        let mut span_data = span(
+            "GET /users/{id}",
+            SpanKind::Server,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users/{id}"),
+                KeyValue::new("http.status_code", 500),
+                KeyValue::new("exception.message", "boom"),
+                KeyValue::new(TOP_LEVEL_TAG, 1),
+            ],
+        );
+        span_data.status = Status::error("");
+        let config = config();
+        let span = DatadogSpan::new(&span_data, &config, &Mapping::default());
+
+        assert_eq!(span.service, "my-service");
+        assert_eq!(span.name, "http.request");
+        assert_eq!(span.resource, "GET /users/{id}");
+        assert_eq!(span.r#type, "web");
+        assert_eq!(span.trace_id, 1);
+        assert_eq!(span.span_id, 2);
+        assert_eq!(span.parent_id, 0);
+        assert_eq!(span.error, 1);
+        assert_eq!(span.meta_value("env"), Some("test"));
+        assert_eq!(span.meta_value("version"), None);
+        assert_eq!(span.meta_value("http.status_code"), Some("500"));
+        assert_eq!(span.meta_value("error.message"), Some("boom"));
+        assert_eq!(span.meta_value("span.kind"), Some("server"));
+        assert_eq!(span.metric_value(SAMPLING_PRIORITY_KEY), Some(1.0));
+        assert_eq!(span.metric_value(TOP_LEVEL_TAG), Some(1.0));
+        assert_eq!(span.metric_value(MEASURED_KEY), None);
+    }
+
+    #[test]
+    fn test_mapping_attributes_are_not_sent_as_meta() {
+        let span_data = span(
+            "charge",
+            SpanKind::Client,
+            vec![
+                KeyValue::new(OPERATION_NAME_KEY, "payments.charge"),
+                KeyValue::new(RESOURCE_NAME_KEY, "charge card"),
+                KeyValue::new(SPAN_TYPE_KEY, "custom"),
+            ],
+        );
+        let config = config();
+        let span = DatadogSpan::new(&span_data, &config, &Mapping::default());
+
+        assert_eq!(span.name, "payments.charge");
+        assert_eq!(span.resource, "charge card");
+        assert_eq!(span.r#type, "custom");
+        assert_eq!(span.meta_value(OPERATION_NAME_KEY), None);
+        assert_eq!(span.meta_value(RESOURCE_NAME_KEY), None);
+        assert_eq!(span.meta_value(SPAN_TYPE_KEY), None);
+        assert_eq!(span.metric_value(MEASURED_KEY), Some(1.0));
+    }
+
+    #[test]
+    fn test_events_meta() {
+        let mut span_data = span("do_something", SpanKind::Internal, vec![]);
+        span_data.events = EvictedQueue::new(1);
+        span_data.events.append_vec(&mut vec![Event::new(
+            "retry",
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_nanos(42),
+            vec![
+                KeyValue::new("attempt", 2),
+                KeyValue::new("reason", "timeout"),
+            ],
+            0,
+        )]);
+        let config = config();
+        let span = DatadogSpan::new(&span_data, &config, &Mapping::default());
+
+        assert_eq!(span.events.len(), 1);
+        assert_eq!(
+            span.meta_value(EVENTS_KEY),
+            Some(
+                r#"[{"attributes":{"attempt":2,"reason":"timeout"},"name":"retry","time_unix_nano":42}]"#
+            )
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Protobuf messages of the Datadog trace intake, used by the agentless exporter.
+//!
+//! Field numbers sourced from https://github.com/DataDog/datadog-agent/tree/main/pkg/proto/datadog/trace
+use std::collections::HashMap;
+
+use super::model::DatadogSpan;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct AgentPayload {
+    #[prost(string, tag = "1")]
+    pub(crate) host_name: String,
+    #[prost(string, tag = "2")]
+    pub(crate) env: String,
+    #[prost(message, repeated, tag = "5")]
+    pub(crate) tracer_payloads: Vec<TracerPayload>,
+    #[prost(map = "string, string", tag = "6")]
+    pub(crate) tags: HashMap<String, String>,
+    #[prost(string, tag = "7")]
+    pub(crate) agent_version: String,
+    #[prost(double, tag = "8")]
+    pub(crate) target_tps: f64,
+    #[prost(double, tag = "9")]
+    pub(crate) error_tps: f64,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct TracerPayload {
+    #[prost(string, tag = "1")]
+    pub(crate) container_id: String,
+    #[prost(string, tag = "2")]
+    pub(crate) language_name: String,
+    #[prost(string, tag = "3")]
+    pub(crate) language_version: String,
+    #[prost(string, tag = "4")]
+    pub(crate) tracer_version: String,
+    #[prost(string, tag = "5")]
+    pub(crate) runtime_id: String,
+    #[prost(message, repeated, tag = "6")]
+    pub(crate) chunks: Vec<TraceChunk>,
+    #[prost(map = "string, string", tag = "7")]
+    pub(crate) tags: HashMap<String, String>,
+    #[prost(string, tag = "8")]
+    pub(crate) env: String,
+    #[prost(string, tag = "9")]
+    pub(crate) hostname: String,
+    #[prost(string, tag = "10")]
+    pub(crate) app_version: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct TraceChunk {
+    #[prost(int32, tag = "1")]
+    pub(crate) priority: i32,
+    #[prost(string, tag = "2")]
+    pub(crate) origin: String,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) spans: Vec<Span>,
+    #[prost(map = "string, string", tag = "4")]
+    pub(crate) tags: HashMap<String, String>,
+    #[prost(bool, tag = "5")]
+    pub(crate) dropped_trace: bool,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub(crate) struct Span {
+    #[prost(string, tag = "1")]
+    pub(crate) service: String,
+    #[prost(string, tag = "2")]
+    pub(crate) name: String,
+    #[prost(string, tag = "3")]
+    pub(crate) resource: String,
+    #[prost(uint64, tag = "4")]
+    pub(crate) trace_id: u64,
+    #[prost(uint64, tag = "5")]
+    pub(crate) span_id: u64,
+    #[prost(uint64, tag = "6")]
+    pub(crate) parent_id: u64,
+    #[prost(int64, tag = "7")]
+    pub(crate) start: i64,
+    #[prost(int64, tag = "8")]
+    pub(crate) duration: i64,
+    #[prost(int32, tag = "9")]
+    pub(crate) error: i32,
+    #[prost(map = "string, string", tag = "10")]
+    pub(crate) meta: HashMap<String, String>,
+    #[prost(map = "string, double", tag = "11")]
+    pub(crate) metrics: HashMap<String, f64>,
+    #[prost(string, tag = "12")]
+    pub(crate) r#type: String,
+}
+
+impl From<&DatadogSpan<'_>> for Span {
+    fn from(span: &DatadogSpan<'_>) -> Self {
+        Span {
+            service: span.service.to_string(),
+            name: span.name.to_string(),
+            resource: span.resource.to_string(),
+            trace_id: span.trace_id,
+            span_id: span.span_id,
+            parent_id: span.parent_id,
+            start: span.start,
+            duration: span.duration,
+            error: span.error,
+            meta: span
+                .meta
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            metrics: span
+                .metrics
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect(),
+            r#type: span.r#type.to_string(),
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Aggregation of spans into stats buckets.
+//!
+//! Spans are added when they end, so the aggregation is kept off the hot path as much as
+//! possible: the stats are computed from the span data without building a Datadog span, only
+//! for top level and measured spans, and aggregated in concentrators sharded by thread.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use opentelemetry::trace::Status;
+use opentelemetry::Value;
+use opentelemetry_sdk::export::trace::SpanData;
+
+use super::sketch::DDSketch;
+use crate::exporter::model::{is_measured_kind, unix_nanos, ModelConfig, MEASURED_KEY};
+use crate::mapping::{attribute, Mapping};
+use crate::processor::TOP_LEVEL_TAG;
+use crate::propagator::DATADOG_ORIGIN_TAG;
+
+/// Duration of a stats bucket, in nanoseconds.
+pub(crate) const BUCKET_DURATION: u64 = 10_000_000_000;
+
+const HTTP_STATUS_CODE_KEYS: [&str; 2] = ["http.status_code", "http.response.status_code"];
+
+/// Dimensions of the stats computed by Datadog.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct AggregationKey {
+    pub(crate) service: String,
+    pub(crate) name: String,
+    pub(crate) resource: String,
+    pub(crate) r#type: String,
+    pub(crate) http_status_code: u32,
+    pub(crate) synthetics: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct GroupedStats {
+    pub(crate) hits: u64,
+    pub(crate) top_level_hits: u64,
+    pub(crate) errors: u64,
+    pub(crate) duration: u64,
+    pub(crate) ok_summary: DDSketch,
+    pub(crate) error_summary: DDSketch,
+}
+
+impl GroupedStats {
+    fn merge(&mut self, other: GroupedStats) {
+        self.hits += other.hits;
+        self.top_level_hits += other.top_level_hits;
+        self.errors += other.errors;
+        self.duration += other.duration;
+        self.ok_summary.merge(other.ok_summary);
+        self.error_summary.merge(other.error_summary);
+    }
+}
+
+/// Stats of the spans ending in a time bucket.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StatsBucket {
+    pub(crate) start: u64,
+    pub(crate) stats: HashMap<AggregationKey, GroupedStats>,
+}
+
+/// What a finished span contributes to the stats.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SpanStats {
+    key: AggregationKey,
+    end: u64,
+    duration: u64,
+    top_level: bool,
+    error: bool,
+}
+
+impl SpanStats {
+    /// Returns the stats of a top level or measured span, `None` for the other spans which are
+    /// not aggregated, as the official tracers do.
+    pub(crate) fn new(span: &SpanData, config: &ModelConfig, mapping: &Mapping) -> Option<Self> {
+        let top_level = metric_value(span, TOP_LEVEL_TAG).is_some_and(|value| value > 0.0);
+        let measured = is_measured_kind(&span.span_kind)
+            || metric_value(span, MEASURED_KEY).is_some_and(|value| value > 0.0);
+        if !top_level && !measured {
+            return None;
+        }
+
+        let start = unix_nanos(span.start_time);
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .map_or(0, |duration| duration.as_nanos() as u64);
+        let key = AggregationKey {
+            service: config.service.clone(),
+            name: mapping.name(span).into_owned(),
+            resource: mapping.resource(span).into_owned(),
+            r#type: mapping.span_type(span).unwrap_or_default().to_string(),
+            http_status_code: HTTP_STATUS_CODE_KEYS
+                .iter()
+                .find_map(|key| http_status_code(span, key))
+                .unwrap_or_default(),
+            synthetics: attribute(span, DATADOG_ORIGIN_TAG)
+                .is_some_and(|origin| origin.starts_with("synthetics")),
+        };
+        Some(SpanStats {
+            key,
+            end: start + duration,
+            duration,
+            top_level,
+            error: matches!(span.status, Status::Error { .. }),
+        })
+    }
+}
+
+// the value of a numeric attribute, sent as a metric
+fn metric_value(span: &SpanData, key: &str) -> Option<f64> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .and_then(|kv| match kv.value {
+            Value::I64(value) => Some(value as f64),
+            Value::F64(value) => Some(value),
+            _ => None,
+        })
+}
+
+fn http_status_code(span: &SpanData, key: &str) -> Option<u32> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .and_then(|kv| match &kv.value {
+            Value::I64(value) => u32::try_from(*value).ok(),
+            Value::String(value) => value.as_str().parse().ok(),
+            _ => None,
+        })
+}
+
+/// Aggregates the hits, errors and latency distributions of spans in
+/// [`BUCKET_DURATION`] buckets, keyed by their start time.
+#[derive(Debug, Default)]
+pub(crate) struct Concentrator {
+    buckets: HashMap<u64, HashMap<AggregationKey, GroupedStats>>,
+}
+
+impl Concentrator {
+    /// Adds the stats of a finished span.
+    pub(crate) fn add(&mut self, span: SpanStats) {
+        let stats = self
+            .buckets
+            .entry(span.end - span.end % BUCKET_DURATION)
+            .or_default()
+            .entry(span.key)
+            .or_default();
+        stats.hits += 1;
+        stats.duration += span.duration;
+        if span.top_level {
+            stats.top_level_hits += 1;
+        }
+        if span.error {
+            stats.errors += 1;
+            stats.error_summary.add(span.duration as f64);
+        } else {
+            stats.ok_summary.add(span.duration as f64);
+        }
+    }
+
+    /// Removes and returns the buckets ended at `now` (in nanoseconds since the epoch),
+    /// or all the buckets if `force` is set.
+    pub(crate) fn flush(&mut self, now: u64, force: bool) -> Vec<StatsBucket> {
+        let flushed = self
+            .buckets
+            .keys()
+            .filter(|start| force || **start + BUCKET_DURATION <= now)
+            .copied()
+            .collect::<Vec<_>>();
+        let mut buckets = flushed
+            .into_iter()
+            .filter_map(|start| {
+                self.buckets
+                    .remove(&start)
+                    .map(|stats| StatsBucket { start, stats })
+            })
+            .collect::<Vec<_>>();
+        buckets.sort_by_key(|bucket| bucket.start);
+        buckets
+    }
+}
+
+// index of the shard of the current thread, assigned in turn to the threads ending spans
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Concentrators sharded by thread, so that spans ending on different threads don't contend
+/// on a single lock. The shards are merged when flushed.
+#[derive(Debug)]
+pub(crate) struct ShardedConcentrator {
+    shards: Box<[Mutex<Concentrator>]>,
+}
+
+impl Default for ShardedConcentrator {
+    fn default() -> Self {
+        let shards = std::thread::available_parallelism().map_or(4, |count| count.get());
+        ShardedConcentrator {
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+}
+
+impl ShardedConcentrator {
+    pub(crate) fn add(&self, span: SpanStats) {
+        let shard = SHARD.with(|shard| *shard) % self.shards.len();
+        self.shards[shard].lock().unwrap().add(span);
+    }
+
+    /// Flushes every shard, see [`Concentrator::flush`].
+    pub(crate) fn flush(&self, now: u64, force: bool) -> Vec<StatsBucket> {
+        let mut merged: HashMap<u64, HashMap<AggregationKey, GroupedStats>> = HashMap::new();
+        for shard in self.shards.iter() {
+            for bucket in shard.lock().unwrap().flush(now, force) {
+                let stats = merged.entry(bucket.start).or_default();
+                for (key, grouped) in bucket.stats {
+                    stats.entry(key).or_default().merge(grouped);
+                }
+            }
+        }
+        let mut buckets = merged
+            .into_iter()
+            .map(|(start, stats)| StatsBucket { start, stats })
+            .collect::<Vec<_>>();
+        buckets.sort_by_key(|bucket| bucket.start);
+        buckets
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::span;
+    use opentelemetry::trace::{SpanKind, Status};
+    use opentelemetry::KeyValue;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_aggregate_top_level_and_measured_spans() {
+        let config = ModelConfig {
+            service: "my-service".to_string(),
+            ..Default::default()
+        };
+        let mapping = Mapping::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let request = |status: i64, duration: u64| {
+            let mut span = span(
+                "GET /users",
+                SpanKind::Server,
+                vec![
+                    KeyValue::new("http.request.method", "GET"),
+                    KeyValue::new("http.route", "/users"),
+                    KeyValue::new("http.status_code", status),
+                    KeyValue::new(TOP_LEVEL_TAG, 1),
+                ],
+            );
+            span.start_time = start;
+            span.end_time = start + Duration::from_millis(duration);
+            if status >= 500 {
+                span.status = Status::error("");
+            }
+            span
+        };
+        let spans = [
+            request(200, 10),
+            request(200, 30),
+            request(500, 20),
+            span("internal", SpanKind::Internal, vec![]),
+        ];
+
+        let concentrator = ShardedConcentrator::default();
+        // the internal span is not aggregated
+        assert_eq!(SpanStats::new(&spans[3], &config, &mapping), None);
+        std::thread::scope(|scope| {
+            for span in &spans {
+                let stats = SpanStats::new(span, &config, &mapping);
+                scope.spawn(|| stats.into_iter().for_each(|stats| concentrator.add(stats)));
+            }
+        });
+
+        let now = 1_000_000 * 1_000_000_000;
+        assert!(concentrator.flush(now, false).is_empty());
+        let buckets = concentrator.flush(now + BUCKET_DURATION, false);
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].start, now);
+        assert_eq!(buckets[0].stats.len(), 2);
+
+        let ok = &buckets[0].stats[&AggregationKey {
+            service: "my-service".to_string(),
+            name: "http.request".to_string(),
+            resource: "GET /users".to_string(),
+            r#type: "web".to_string(),
+            http_status_code: 200,
+            synthetics: false,
+        }];
+        assert_eq!(ok.hits, 2);
+        assert_eq!(ok.top_level_hits, 2);
+        assert_eq!(ok.errors, 0);
+        assert_eq!(ok.duration, 40_000_000);
+        assert_eq!(ok.ok_summary.count(), 2.0);
+
+        let error = buckets[0]
+            .stats
+            .iter()
+            .find(|(key, _)| key.http_status_code == 500)
+            .unwrap()
+            .1;
+        assert_eq!(error.errors, 1);
+        assert_eq!(error.error_summary.count(), 1.0);
+        assert!(concentrator.flush(u64::MAX, true).is_empty());
+    }
+}
@@ -0,0 +1,387 @@
+//! Client side computation of APM stats.
+//!
+//! Datadog computes the trace metrics (hits, errors and latency distributions) from the spans
+//! received by the agent, which are inaccurate once traces are sampled. Like the official tracers,
+//! the [`StatsProcessor`] aggregates every finished span in 10s buckets and sends the stats to
+//! the agent `/v0.6/stats` endpoint. Traces are then sent with the `Datadog-Client-Computed-Stats`
+//! header, so the agent doesn't compute the stats again and unsampled traces can be dropped.
+//!
+//! The processor wraps the batch span processor, so that the stats are computed from the span
+//! data it receives instead of another copy of it, as every span processor gets its own.
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, SystemTime};
+
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use opentelemetry::trace::{TraceError, TraceResult};
+use opentelemetry::{global, Context};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::runtime::{Runtime, RuntimeChannel, Tokio, TrySend};
+use opentelemetry_sdk::trace::{Span, SpanProcessor};
+use rmp::encode;
+
+use super::model::ModelConfig;
+use super::{Error, DATADOG_META_LANG_HEADER, DATADOG_META_TRACER_VERSION_HEADER};
+use crate::agent::{SharedFeatures, STATS_PATH};
+use crate::mapping::Mapping;
+use crate::processor::runtime_id;
+
+mod concentrator;
+mod sketch;
+
+use concentrator::{ShardedConcentrator, SpanStats, StatsBucket, BUCKET_DURATION};
+
+/// Header informing the agent that the stats are computed by the tracer.
+pub(crate) const CLIENT_COMPUTED_STATS_HEADER: &str = "Datadog-Client-Computed-Stats";
+
+/// Maximum time `force_flush` and `shutdown` wait for the stats to be sent.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct StatsExporter {
+    client: reqwest::Client,
+    request_url: String,
+    model_config: ModelConfig,
+    sequence: AtomicU64,
+}
+
+impl StatsExporter {
+    fn encode(&self, buckets: &[StatsBucket]) -> Result<Vec<u8>, Error> {
+        let config = &self.model_config;
+        let mut data = Vec::new();
+        encode::write_map_len(&mut data, 9)?;
+        encode::write_str(&mut data, "Hostname")?;
+        encode::write_str(&mut data, "")?;
+        encode::write_str(&mut data, "Env")?;
+        encode::write_str(&mut data, config.env.as_deref().unwrap_or_default())?;
+        encode::write_str(&mut data, "Version")?;
+        encode::write_str(&mut data, config.version.as_deref().unwrap_or_default())?;
+        encode::write_str(&mut data, "Service")?;
+        encode::write_str(&mut data, &config.service)?;
+        encode::write_str(&mut data, "Lang")?;
+        encode::write_str(&mut data, "rust")?;
+        encode::write_str(&mut data, "TracerVersion")?;
+        encode::write_str(&mut data, env!("CARGO_PKG_VERSION"))?;
+        encode::write_str(&mut data, "RuntimeID")?;
+        encode::write_str(&mut data, runtime_id())?;
+        encode::write_str(&mut data, "Sequence")?;
+        encode::write_uint(&mut data, self.sequence.fetch_add(1, Ordering::Relaxed) + 1)?;
+
+        encode::write_str(&mut data, "Stats")?;
+        encode::write_array_len(&mut data, buckets.len() as u32)?;
+        for bucket in buckets {
+            encode::write_map_len(&mut data, 3)?;
+            encode::write_str(&mut data, "Start")?;
+            encode::write_uint(&mut data, bucket.start)?;
+            encode::write_str(&mut data, "Duration")?;
+            encode::write_uint(&mut data, BUCKET_DURATION)?;
+            encode::write_str(&mut data, "Stats")?;
+            encode::write_array_len(&mut data, bucket.stats.len() as u32)?;
+            for (key, stats) in &bucket.stats {
+                encode::write_map_len(&mut data, 12)?;
+                encode::write_str(&mut data, "Service")?;
+                encode::write_str(&mut data, &key.service)?;
+                encode::write_str(&mut data, "Name")?;
+                encode::write_str(&mut data, &key.name)?;
+                encode::write_str(&mut data, "Resource")?;
+                encode::write_str(&mut data, &key.resource)?;
+                encode::write_str(&mut data, "HTTPStatusCode")?;
+                encode::write_uint(&mut data, key.http_status_code as u64)?;
+                encode::write_str(&mut data, "Type")?;
+                encode::write_str(&mut data, &key.r#type)?;
+                encode::write_str(&mut data, "Synthetics")?;
+                encode::write_bool(&mut data, key.synthetics).map_err(|_| Error::MessagePack)?;
+                encode::write_str(&mut data, "Hits")?;
+                encode::write_uint(&mut data, stats.hits)?;
+                encode::write_str(&mut data, "TopLevelHits")?;
+                encode::write_uint(&mut data, stats.top_level_hits)?;
+                encode::write_str(&mut data, "Errors")?;
+                encode::write_uint(&mut data, stats.errors)?;
+                encode::write_str(&mut data, "Duration")?;
+                encode::write_uint(&mut data, stats.duration)?;
+                encode::write_str(&mut data, "OkSummary")?;
+                encode::write_bin(&mut data, &stats.ok_summary.encode_to_vec())?;
+                encode::write_str(&mut data, "ErrorSummary")?;
+                encode::write_bin(&mut data, &stats.error_summary.encode_to_vec())?;
+            }
+        }
+        Ok(data)
+    }
+
+    async fn export(&self, buckets: Vec<StatsBucket>) -> Result<(), Error> {
+        if buckets.is_empty() {
+            return Ok(());
+        }
+        let data = self.encode(&buckets)?;
+        self.client
+            .post(&self.request_url)
+            .header(reqwest::header::CONTENT_TYPE, "application/msgpack")
+            .header(DATADOG_META_LANG_HEADER, "rust")
+            .header(
+                DATADOG_META_TRACER_VERSION_HEADER,
+                env!("CARGO_PKG_VERSION"),
+            )
+            .body(data)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|now| now.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Message sent to the flush task, acknowledged once the stats are sent.
+#[derive(Debug)]
+enum Message {
+    Flush(mpsc::SyncSender<TraceResult<()>>),
+    Shutdown(mpsc::SyncSender<TraceResult<()>>),
+}
+
+/// Span processor aggregating the stats of the finished spans, sent to the agent every 10s,
+/// before passing them to the wrapped processor.
+///
+/// Stats are only sent by a task on the Tokio runtime, so flushing from async code doesn't
+/// drive the request outside of the runtime.
+pub(crate) struct StatsProcessor {
+    inner: Box<dyn SpanProcessor>,
+    concentrator: Arc<ShardedConcentrator>,
+    features: SharedFeatures,
+    model_config: ModelConfig,
+    mapping: Mapping,
+    request_url: String,
+    messages: <Tokio as RuntimeChannel>::Sender<Message>,
+}
+
+impl Debug for StatsProcessor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StatsProcessor")
+            .field("inner", &self.inner)
+            .field("request_url", &self.request_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StatsProcessor {
+    /// Creates the processor wrapping `inner` and spawns its flush task on the Tokio runtime.
+    ///
+    /// Nothing is aggregated nor sent once the agent is discovered not to support stats.
+    pub(crate) fn new(
+        inner: impl SpanProcessor + 'static,
+        client: reqwest::Client,
+        agent_endpoint: &str,
+        features: SharedFeatures,
+        model_config: ModelConfig,
+        mapping: Mapping,
+    ) -> Self {
+        let exporter = StatsExporter {
+            client,
+            request_url: format!("{}{STATS_PATH}", agent_endpoint.trim_end_matches('/')),
+            model_config: model_config.clone(),
+            sequence: AtomicU64::new(0),
+        };
+        let (sender, receiver) = Tokio.batch_message_channel(1);
+        let processor = StatsProcessor {
+            inner: Box::new(inner),
+            concentrator: Arc::default(),
+            features,
+            model_config,
+            mapping,
+            request_url: exporter.request_url.clone(),
+            messages: sender,
+        };
+
+        let concentrator = processor.concentrator.clone();
+        let features = processor.features.clone();
+        Tokio.spawn(Box::pin(async move {
+            let mut ticks = Tokio
+                .interval(Duration::from_nanos(BUCKET_DURATION))
+                .skip(1);
+            let mut messages = receiver;
+            loop {
+                let message = match future::select(ticks.next(), messages.next()).await {
+                    Either::Left(_) => None,
+                    Either::Right((Some(message), _)) => Some(message),
+                    // the processor is dropped
+                    Either::Right((None, _)) => break,
+                };
+                let force = message.is_some();
+                let buckets = concentrator.flush(now(), force);
+                let result = if features.read().unwrap().client_computed_stats {
+                    exporter.export(buckets).await.map_err(TraceError::from)
+                } else {
+                    Ok(())
+                };
+                match message {
+                    None => {
+                        if let Err(err) = result {
+                            global::handle_error(err);
+                        }
+                    }
+                    Some(Message::Flush(ack)) => {
+                        let _ = ack.send(result);
+                    }
+                    Some(Message::Shutdown(ack)) => {
+                        let _ = ack.send(result);
+                        break;
+                    }
+                }
+            }
+        }));
+
+        processor
+    }
+
+    fn enabled(&self) -> bool {
+        self.features.read().unwrap().client_computed_stats
+    }
+
+    // waits for the flush task to send the stats, which can't happen if it runs on the thread
+    // blocked here, e.g. on a current thread runtime, hence the timeout
+    fn flush_all(
+        &self,
+        message: fn(mpsc::SyncSender<TraceResult<()>>) -> Message,
+    ) -> TraceResult<()> {
+        let (ack, result) = mpsc::sync_channel(1);
+        TrySend::try_send(&self.messages, message(ack))
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+        result
+            .recv_timeout(FLUSH_TIMEOUT)
+            .map_err(|_| TraceError::ExportTimedOut(FLUSH_TIMEOUT))?
+    }
+}
+
+impl SpanProcessor for StatsProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        // most spans are neither top level nor measured, so the shared features are only read
+        // for the aggregated ones
+        if let Some(stats) = SpanStats::new(&span, &self.model_config, &self.mapping) {
+            if self.enabled() {
+                self.concentrator.add(stats);
+            }
+        }
+        self.inner.on_end(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        let result = self.inner.force_flush();
+        self.flush_all(Message::Flush).and(result)
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        let result = self.inner.shutdown();
+        self.flush_all(Message::Shutdown).and(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentFeatures;
+    use crate::fixtures::span;
+    use crate::processor::{DatadogSpanProcessor, TOP_LEVEL_TAG};
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry::KeyValue;
+    use rmpv::Value;
+
+    #[test]
+    fn test_encode_stats_payload() {
+        let model_config = ModelConfig {
+            service: "my-service".to_string(),
+            env: Some("test".to_string()),
+            ..Default::default()
+        };
+        let exporter = StatsExporter {
+            client: reqwest::Client::new(),
+            request_url: String::new(),
+            model_config: model_config.clone(),
+            sequence: AtomicU64::new(0),
+        };
+        let span_data = span(
+            "do_something",
+            SpanKind::Internal,
+            vec![KeyValue::new(TOP_LEVEL_TAG, 1)],
+        );
+        let concentrator = ShardedConcentrator::default();
+        concentrator.add(SpanStats::new(&span_data, &model_config, &Mapping::default()).unwrap());
+
+        let data = exporter
+            .encode(&concentrator.flush(u64::MAX, true))
+            .unwrap();
+
+        let payload = rmpv::decode::read_value(&mut data.as_slice()).unwrap();
+        let field = |value: &Value, name: &str| {
+            value
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(name))
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        assert_eq!(field(&payload, "Env").as_str(), Some("test"));
+        assert_eq!(field(&payload, "Sequence").as_u64(), Some(1));
+        let bucket = &field(&payload, "Stats")[0];
+        assert_eq!(field(bucket, "Duration").as_u64(), Some(BUCKET_DURATION));
+        let stats = &field(bucket, "Stats")[0];
+        assert_eq!(field(stats, "Service").as_str(), Some("my-service"));
+        assert_eq!(field(stats, "Resource").as_str(), Some("do_something"));
+        assert_eq!(field(stats, "Hits").as_u64(), Some(1));
+        assert_eq!(field(stats, "TopLevelHits").as_u64(), Some(1));
+        assert!(field(stats, "OkSummary").as_slice().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_flush_from_async_code() {
+        use hyper::service::{make_service_fn, service_fn};
+        use hyper::{Body, Request, Response, Server};
+        use std::convert::Infallible;
+
+        let (sender, requests) = mpsc::channel();
+        let make_service = make_service_fn(move |_| {
+            let sender = sender.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let _ = sender.send(request.uri().path().to_string());
+                    async { Ok::<_, Infallible>(Response::new(Body::empty())) }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let endpoint = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let features = AgentFeatures::resolve(None, true, None);
+        let mut processor = StatsProcessor::new(
+            DatadogSpanProcessor,
+            reqwest::Client::new(),
+            &endpoint,
+            Arc::new(std::sync::RwLock::new(features)),
+            ModelConfig::default(),
+            Mapping::default(),
+        );
+        processor.on_end(span(
+            "do_something",
+            SpanKind::Internal,
+            vec![KeyValue::new(TOP_LEVEL_TAG, 1)],
+        ));
+
+        // the stats are sent by the flush task, not by the runtime thread blocked here
+        processor.force_flush().unwrap();
+        assert_eq!(requests.try_recv().as_deref(), Ok(STATS_PATH));
+        processor.shutdown().unwrap();
+        assert!(requests.try_recv().is_err());
+        // the flush task is stopped
+        assert!(processor.force_flush().is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Minimal DDSketch, encoded with the protobuf format expected by the Datadog agent.
+//!
+//! Values are mapped to logarithmic bins guaranteeing a 1% relative accuracy on quantiles,
+//! using the same mapping as [sketches-go](https://github.com/DataDog/sketches-go)
+//! (`index = floor(ln(value) / ln(gamma))`).
+use std::collections::HashMap;
+
+use prost::Message;
+
+const RELATIVE_ACCURACY: f64 = 0.01;
+// values below this threshold are counted as zeros
+const MIN_INDEXABLE_VALUE: f64 = 1e-9;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct DDSketch {
+    bins: HashMap<i32, f64>,
+    zero_count: f64,
+}
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+impl DDSketch {
+    pub(crate) fn add(&mut self, value: f64) {
+        if value < MIN_INDEXABLE_VALUE {
+            self.zero_count += 1.0;
+        } else {
+            let index = (value.ln() / gamma().ln()).floor() as i32;
+            *self.bins.entry(index).or_default() += 1.0;
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: DDSketch) {
+        for (index, count) in other.bins {
+            *self.bins.entry(index).or_default() += count;
+        }
+        self.zero_count += other.zero_count;
+    }
+
+    #[cfg(test)]
+    pub(crate) fn count(&self) -> f64 {
+        self.zero_count + self.bins.values().sum::<f64>()
+    }
+
+    pub(crate) fn encode_to_vec(&self) -> Vec<u8> {
+        pb::DDSketch {
+            mapping: Some(pb::IndexMapping {
+                gamma: gamma(),
+                index_offset: 0.0,
+                interpolation: 0,
+            }),
+            positive_values: Some(pb::Store {
+                bin_counts: self.bins.clone(),
+                ..Default::default()
+            }),
+            negative_values: None,
+            zero_count: self.zero_count,
+        }
+        .encode_to_vec()
+    }
+
+    #[cfg(test)]
+    pub(crate) fn quantile(&self, quantile: f64) -> f64 {
+        let rank = quantile * (self.count() - 1.0);
+        if rank < self.zero_count {
+            return 0.0;
+        }
+        let mut bins = self.bins.iter().collect::<Vec<_>>();
+        bins.sort_by_key(|(index, _)| **index);
+        let mut count = self.zero_count;
+        for (index, bin_count) in bins {
+            count += bin_count;
+            if count > rank {
+                // middle of the bin, in the relative accuracy sense
+                return gamma().powi(*index) * 2.0 * gamma() / (1.0 + gamma());
+            }
+        }
+        f64::NAN
+    }
+}
+
+mod pb {
+    use std::collections::HashMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct DDSketch {
+        #[prost(message, optional, tag = "1")]
+        pub(super) mapping: Option<IndexMapping>,
+        #[prost(message, optional, tag = "2")]
+        pub(super) positive_values: Option<Store>,
+        #[prost(message, optional, tag = "3")]
+        pub(super) negative_values: Option<Store>,
+        #[prost(double, tag = "4")]
+        pub(super) zero_count: f64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct IndexMapping {
+        #[prost(double, tag = "1")]
+        pub(super) gamma: f64,
+        #[prost(double, tag = "2")]
+        pub(super) index_offset: f64,
+        // NONE
+        #[prost(int32, tag = "3")]
+        pub(super) interpolation: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub(super) struct Store {
+        #[prost(map = "sint32, double", tag = "1")]
+        pub(super) bin_counts: HashMap<i32, f64>,
+        #[prost(double, repeated, tag = "2")]
+        pub(super) contiguous_bin_counts: Vec<f64>,
+        #[prost(sint32, tag = "3")]
+        pub(super) contiguous_bin_index_offset: i32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sketch_relative_accuracy() {
+        let mut sketch = DDSketch::default();
+        for value in 1..=1000 {
+            sketch.add(value as f64 * 1_000_000.0);
+        }
+        sketch.add(0.0);
+
+        assert_eq!(sketch.count(), 1001.0);
+        for (quantile, expected) in [(0.5, 500_000_000.0), (0.99, 990_000_000.0)] {
+            let value = sketch.quantile(quantile);
+            assert!(
+                (value - expected).abs() / expected <= RELATIVE_ACCURACY,
+                "{quantile}: {value}"
+            );
+        }
+        assert_eq!(sketch.quantile(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_sketch_encoding() {
+        let mut sketch = DDSketch::default();
+        sketch.add(1.0);
+        sketch.add(1.0);
+        sketch.add(0.0);
+
+        let decoded = pb::DDSketch::decode(sketch.encode_to_vec().as_slice()).unwrap();
+        assert_eq!(decoded.zero_count, 1.0);
+        assert_eq!(decoded.mapping.unwrap().gamma, gamma());
+        assert_eq!(
+            decoded.positive_values.unwrap().bin_counts,
+            HashMap::from([(0, 2.0)])
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use opentelemetry::{Array, Value};
+
+use super::model::{unix_nanos, DatadogSpan, EVENTS_KEY};
+use super::Error;
+
+const SPAN_NUM_ELEMENTS: u32 = 12;
+
+// Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/main/pkg/trace/api/version.go
+//
+// The payload is an array of traces, where each trace is an array of spans. A span is encoded
+// as a map having exactly 12 elements: service, name, resource, trace_id, span_id, parent_id,
+// start, duration, error, meta, metrics and type.
+//
+// Agents supporting span events also accept a 13th `span_events` element, in which case the
+// events are not duplicated in the `events` meta.
+pub(crate) fn encode(traces: &[Vec<DatadogSpan>], span_events: bool) -> Result<Vec<u8>, Error> {
+    let mut encoded = Vec::new();
+    rmp::encode::write_array_len(&mut encoded, traces.len() as u32)?;
+
+    for trace in traces {
+        rmp::encode::write_array_len(&mut encoded, trace.len() as u32)?;
+
+        for span in trace {
+            let native_events = span_events && !span.events.is_empty();
+            let num_elements = SPAN_NUM_ELEMENTS + u32::from(native_events);
+            rmp::encode::write_map_len(&mut encoded, num_elements)?;
+            rmp::encode::write_str(&mut encoded, "service")?;
+            rmp::encode::write_str(&mut encoded, span.service)?;
+            rmp::encode::write_str(&mut encoded, "name")?;
+            rmp::encode::write_str(&mut encoded, &span.name)?;
+            rmp::encode::write_str(&mut encoded, "resource")?;
+            rmp::encode::write_str(&mut encoded, &span.resource)?;
+            rmp::encode::write_str(&mut encoded, "trace_id")?;
+            rmp::encode::write_u64(&mut encoded, span.trace_id)?;
+            rmp::encode::write_str(&mut encoded, "span_id")?;
+            rmp::encode::write_u64(&mut encoded, span.span_id)?;
+            rmp::encode::write_str(&mut encoded, "parent_id")?;
+            rmp::encode::write_u64(&mut encoded, span.parent_id)?;
+            rmp::encode::write_str(&mut encoded, "start")?;
+            rmp::encode::write_i64(&mut encoded, span.start)?;
+            rmp::encode::write_str(&mut encoded, "duration")?;
+            rmp::encode::write_i64(&mut encoded, span.duration)?;
+            rmp::encode::write_str(&mut encoded, "error")?;
+            rmp::encode::write_i32(&mut encoded, span.error)?;
+            rmp::encode::write_str(&mut encoded, "meta")?;
+            let meta = span
+                .meta
+                .iter()
+                .filter(|(key, _)| !native_events || key != EVENTS_KEY)
+                .collect::<Vec<_>>();
+            rmp::encode::write_map_len(&mut encoded, meta.len() as u32)?;
+            for (key, value) in meta {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_str(&mut encoded, value)?;
+            }
+            rmp::encode::write_str(&mut encoded, "metrics")?;
+            rmp::encode::write_map_len(&mut encoded, span.metrics.len() as u32)?;
+            for (key, value) in &span.metrics {
+                rmp::encode::write_str(&mut encoded, key)?;
+                rmp::encode::write_f64(&mut encoded, *value)?;
+            }
+            rmp::encode::write_str(&mut encoded, "type")?;
+            rmp::encode::write_str(&mut encoded, span.r#type)?;
+            if native_events {
+                rmp::encode::write_str(&mut encoded, "span_events")?;
+                encode_span_events(&mut encoded, span)?;
+            }
+        }
+    }
+
+    Ok(encoded)
+}
+
+// Span events are maps of name, time_unix_nano and attributes, whose values are maps of their
+// type (0: string, 1: bool, 2: int, 3: double, 4: array) and the value of this type.
+fn encode_span_events(encoded: &mut Vec<u8>, span: &DatadogSpan) -> Result<(), Error> {
+    rmp::encode::write_array_len(encoded, span.events.len() as u32)?;
+    for event in &span.events {
+        rmp::encode::write_map_len(encoded, 3)?;
+        rmp::encode::write_str(encoded, "name")?;
+        rmp::encode::write_str(encoded, &event.name)?;
+        rmp::encode::write_str(encoded, "time_unix_nano")?;
+        rmp::encode::write_u64(encoded, unix_nanos(event.timestamp))?;
+        rmp::encode::write_str(encoded, "attributes")?;
+        rmp::encode::write_map_len(encoded, event.attributes.len() as u32)?;
+        for kv in &event.attributes {
+            rmp::encode::write_str(encoded, kv.key.as_str())?;
+            match &kv.value {
+                Value::Array(array) => {
+                    rmp::encode::write_map_len(encoded, 2)?;
+                    rmp::encode::write_str(encoded, "type")?;
+                    rmp::encode::write_u8(encoded, 4)?;
+                    rmp::encode::write_str(encoded, "array_value")?;
+                    rmp::encode::write_map_len(encoded, 1)?;
+                    rmp::encode::write_str(encoded, "values")?;
+                    let values: Vec<Value> = match array {
+                        Array::Bool(values) => values.iter().map(|v| Value::Bool(*v)).collect(),
+                        Array::I64(values) => values.iter().map(|v| Value::I64(*v)).collect(),
+                        Array::F64(values) => values.iter().map(|v| Value::F64(*v)).collect(),
+                        Array::String(values) => {
+                            values.iter().cloned().map(Value::String).collect()
+                        }
+                    };
+                    rmp::encode::write_array_len(encoded, values.len() as u32)?;
+                    for value in &values {
+                        encode_attribute(encoded, value)?;
+                    }
+                }
+                value => encode_attribute(encoded, value)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn encode_attribute(encoded: &mut Vec<u8>, value: &Value) -> Result<(), Error> {
+    rmp::encode::write_map_len(encoded, 2)?;
+    rmp::encode::write_str(encoded, "type")?;
+    match value {
+        Value::String(value) => {
+            rmp::encode::write_u8(encoded, 0)?;
+            rmp::encode::write_str(encoded, "string_value")?;
+            rmp::encode::write_str(encoded, value.as_str())?;
+        }
+        Value::Bool(value) => {
+            rmp::encode::write_u8(encoded, 1)?;
+            rmp::encode::write_str(encoded, "bool_value")?;
+            rmp::encode::write_bool(encoded, *value).map_err(|_| Error::MessagePack)?;
+        }
+        Value::I64(value) => {
+            rmp::encode::write_u8(encoded, 2)?;
+            rmp::encode::write_str(encoded, "int_value")?;
+            rmp::encode::write_i64(encoded, *value)?;
+        }
+        Value::F64(value) => {
+            rmp::encode::write_u8(encoded, 3)?;
+            rmp::encode::write_str(encoded, "double_value")?;
+            rmp::encode::write_f64(encoded, *value)?;
+        }
+        // nested arrays are not supported by the agent
+        Value::Array(array) => {
+            rmp::encode::write_u8(encoded, 0)?;
+            rmp::encode::write_str(encoded, "string_value")?;
+            rmp::encode::write_str(encoded, &array.to_string())?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use super::model::DatadogSpan;
+use super::Error;
+
+const SPAN_NUM_ELEMENTS: u32 = 12;
+
+// Protocol documentation sourced from https://github.com/DataDog/datadog-agent/blob/main/pkg/trace/api/version.go
+//
+// The payload is an array containing exactly 2 elements:
+//
+// 	1. An array of all unique strings present in the payload (a dictionary referred to by index).
+// 	2. An array of traces, where each trace is an array of spans. A span is encoded as an array having
+// 	   exactly 12 elements, representing all span properties, in this exact order:
+//
+// 		 0: Service   (uint32)
+// 		 1: Name      (uint32)
+// 		 2: Resource  (uint32)
+// 		 3: TraceID   (uint64)
+// 		 4: SpanID    (uint64)
+// 		 5: ParentID  (uint64)
+// 		 6: Start     (int64)
+// 		 7: Duration  (int64)
+// 		 8: Error     (int32)
+// 		 9: Meta      (map[uint32]uint32)
+// 		10: Metrics   (map[uint32]float64)
+// 		11: Type      (uint32)
+//
+// 	The "uint32" typed values in "Service", "Name", "Resource", "Type", "Meta" and "Metrics" represent
+// 	the index at which the corresponding string is found in the dictionary.
+pub(crate) fn encode(traces: &[Vec<DatadogSpan>]) -> Result<Vec<u8>, Error> {
+    let mut interner = StringInterner::default();
+    // the empty string is always part of the dictionary, as the zero-value of unset fields
+    interner.intern("");
+
+    let mut encoded_traces = Vec::new();
+    rmp::encode::write_array_len(&mut encoded_traces, traces.len() as u32)?;
+
+    for trace in traces {
+        rmp::encode::write_array_len(&mut encoded_traces, trace.len() as u32)?;
+
+        for span in trace {
+            let encoded = &mut encoded_traces;
+            rmp::encode::write_array_len(encoded, SPAN_NUM_ELEMENTS)?;
+            rmp::encode::write_u32(encoded, interner.intern(span.service))?;
+            rmp::encode::write_u32(encoded, interner.intern(&span.name))?;
+            rmp::encode::write_u32(encoded, interner.intern(&span.resource))?;
+            rmp::encode::write_u64(encoded, span.trace_id)?;
+            rmp::encode::write_u64(encoded, span.span_id)?;
+            rmp::encode::write_u64(encoded, span.parent_id)?;
+            rmp::encode::write_i64(encoded, span.start)?;
+            rmp::encode::write_i64(encoded, span.duration)?;
+            rmp::encode::write_i32(encoded, span.error)?;
+            rmp::encode::write_map_len(encoded, span.meta.len() as u32)?;
+            for (key, value) in &span.meta {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_u32(encoded, interner.intern(value))?;
+            }
+            rmp::encode::write_map_len(encoded, span.metrics.len() as u32)?;
+            for (key, value) in &span.metrics {
+                rmp::encode::write_u32(encoded, interner.intern(key))?;
+                rmp::encode::write_f64(encoded, *value)?;
+            }
+            rmp::encode::write_u32(encoded, interner.intern(span.r#type))?;
+        }
+    }
+
+    let mut payload = Vec::with_capacity(encoded_traces.len());
+    rmp::encode::write_array_len(&mut payload, 2)?;
+    rmp::encode::write_array_len(&mut payload, interner.strings.len() as u32)?;
+    for string in &interner.strings {
+        rmp::encode::write_str(&mut payload, string)?;
+    }
+    payload.append(&mut encoded_traces);
+
+    Ok(payload)
+}
+
+#[derive(Default)]
+struct StringInterner {
+    indexes: HashMap<String, u32>,
+    strings: Vec<String>,
+}
+
+impl StringInterner {
+    fn intern(&mut self, data: &str) -> u32 {
+        if let Some(index) = self.indexes.get(data) {
+            return *index;
+        }
+        let index = self.strings.len() as u32;
+        self.strings.push(data.to_string());
+        self.indexes.insert(data.to_string(), index);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StringInterner;
+
+    #[test]
+    fn test_intern() {
+        let mut interner = StringInterner::default();
+
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.intern("b"), 1);
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.strings, vec!["a", "b"]);
+    }
+}
@@ -0,0 +1,55 @@
+//! Span fixtures shared by the unit tests.
+use std::borrow::Cow;
+use std::time::SystemTime;
+
+use opentelemetry::trace::{
+    SpanContext, SpanId, SpanKind, Status, TraceFlags, TraceId, TraceState,
+};
+use opentelemetry::{InstrumentationLibrary, KeyValue};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::EvictedQueue;
+use opentelemetry_sdk::Resource;
+
+/// A sampled root span of the trace `1` with the ID `2`.
+pub(crate) fn span(name: &'static str, kind: SpanKind, attributes: Vec<KeyValue>) -> SpanData {
+    let now = SystemTime::now();
+    SpanData {
+        span_context: span_context(1, 2),
+        parent_span_id: SpanId::INVALID,
+        span_kind: kind,
+        name: name.into(),
+        start_time: now,
+        end_time: now,
+        attributes,
+        dropped_attributes_count: 0,
+        events: EvictedQueue::new(0),
+        links: EvictedQueue::new(0),
+        status: Status::Unset,
+        resource: Cow::Owned(Resource::empty()),
+        instrumentation_lib: InstrumentationLibrary::new(
+            "opentelemetry-datadog",
+            None::<&str>,
+            None::<&str>,
+            None,
+        ),
+    }
+}
+
+/// An internal `do_something` span with the given IDs, a root span if `parent_id` is `0`.
+pub(crate) fn span_with_ids(trace_id: u128, span_id: u64, parent_id: u64) -> SpanData {
+    SpanData {
+        span_context: span_context(trace_id, span_id),
+        parent_span_id: SpanId::from(parent_id),
+        ..span("do_something", SpanKind::Internal, vec![])
+    }
+}
+
+fn span_context(trace_id: u128, span_id: u64) -> SpanContext {
+    SpanContext::new(
+        TraceId::from(trace_id),
+        SpanId::from(span_id),
+        TraceFlags::SAMPLED,
+        false,
+        TraceState::default(),
+    )
+}
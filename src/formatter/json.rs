@@ -0,0 +1,281 @@
+//! A minimal JSON writer appending to a `String`, used instead of `serde_json` to format events
+//! without intermediate allocations nor UTF-8 validation of the output.
+use std::fmt::{self, Debug, Display, Write};
+
+use serde_json::Value;
+use tracing::field::{Field, Visit};
+
+/// A JSON object being written, closed by [`JsonObject::end`].
+pub(crate) struct JsonObject<'a> {
+    buf: &'a mut String,
+    empty: bool,
+}
+
+impl<'a> JsonObject<'a> {
+    pub(crate) fn new(buf: &'a mut String) -> Self {
+        buf.push('{');
+        JsonObject { buf, empty: true }
+    }
+
+    /// Starts an entry whose key is escaped.
+    pub(crate) fn key(&mut self, key: &str) -> JsonValue<'_> {
+        self.separator();
+        self.buf.push('"');
+        escape(self.buf, key);
+        self.buf.push_str("\":");
+        JsonValue(self.buf)
+    }
+
+    /// Starts an entry whose key is a literal which needs no escaping.
+    pub(crate) fn static_key(&mut self, key: &'static str) -> JsonValue<'_> {
+        debug_assert!(!key.bytes().any(needs_escape));
+        self.separator();
+        self.buf.push('"');
+        self.buf.push_str(key);
+        self.buf.push_str("\":");
+        JsonValue(self.buf)
+    }
+
+    /// Writes a string entry with `f`, or no entry if it writes nothing.
+    pub(crate) fn str_with(
+        &mut self,
+        key: &'static str,
+        f: impl FnOnce(&mut dyn Write) -> fmt::Result,
+    ) -> fmt::Result {
+        let (len, empty) = (self.buf.len(), self.empty);
+        self.static_key(key).0.push('"');
+        let start = self.buf.len();
+        f(&mut Escaper(self.buf))?;
+        if self.buf.len() == start {
+            self.buf.truncate(len);
+            self.empty = empty;
+        } else {
+            self.buf.push('"');
+        }
+        Ok(())
+    }
+
+    /// Appends entries already written as JSON, without their braces.
+    pub(crate) fn raw_entries(&mut self, entries: &str) {
+        self.separator();
+        self.buf.push_str(entries);
+    }
+
+    pub(crate) fn end(self) {
+        self.buf.push('}');
+    }
+
+    fn separator(&mut self) {
+        if !self.empty {
+            self.buf.push(',');
+        }
+        self.empty = false;
+    }
+}
+
+/// A JSON array being written, closed by [`JsonArray::end`].
+pub(crate) struct JsonArray<'a> {
+    buf: &'a mut String,
+    empty: bool,
+}
+
+impl JsonArray<'_> {
+    pub(crate) fn element(&mut self) -> JsonValue<'_> {
+        if !self.empty {
+            self.buf.push(',');
+        }
+        self.empty = false;
+        JsonValue(self.buf)
+    }
+
+    pub(crate) fn end(self) {
+        self.buf.push(']');
+    }
+}
+
+/// The value of an object entry or array element.
+pub(crate) struct JsonValue<'a>(&'a mut String);
+
+impl<'a> JsonValue<'a> {
+    pub(crate) fn str(self, value: &str) {
+        self.0.push('"');
+        escape(self.0, value);
+        self.0.push('"');
+    }
+
+    /// Writes the `Display` representation of `value` as a string.
+    pub(crate) fn display(self, value: &dyn Display) {
+        self.0.push('"');
+        // writing to a `String` never fails
+        let _ = write!(Escaper(self.0), "{value}");
+        self.0.push('"');
+    }
+
+    /// Writes the `Debug` representation of `value` as a string.
+    pub(crate) fn debug(self, value: &dyn Debug) {
+        self.0.push('"');
+        let _ = write!(Escaper(self.0), "{value:?}");
+        self.0.push('"');
+    }
+
+    pub(crate) fn bool(self, value: bool) {
+        self.0.push_str(if value { "true" } else { "false" });
+    }
+
+    pub(crate) fn u64(self, value: u64) {
+        self.0.push_str(itoa::Buffer::new().format(value));
+    }
+
+    pub(crate) fn i64(self, value: i64) {
+        self.0.push_str(itoa::Buffer::new().format(value));
+    }
+
+    /// Writes `null` for NaN and infinite values, like `serde_json`.
+    pub(crate) fn f64(self, value: f64) {
+        if value.is_finite() {
+            self.0.push_str(ryu::Buffer::new().format_finite(value));
+        } else {
+            self.0.push_str("null");
+        }
+    }
+
+    pub(crate) fn value(self, value: &Value) {
+        match value {
+            Value::Null => self.0.push_str("null"),
+            Value::Bool(value) => self.bool(*value),
+            Value::Number(number) => {
+                let _ = write!(self.0, "{number}");
+            }
+            Value::String(value) => self.str(value),
+            Value::Array(values) => {
+                let mut array = self.array();
+                for value in values {
+                    array.element().value(value);
+                }
+                array.end();
+            }
+            Value::Object(map) => {
+                let mut object = self.object();
+                for (key, value) in map {
+                    object.key(key).value(value);
+                }
+                object.end();
+            }
+        }
+    }
+
+    pub(crate) fn object(self) -> JsonObject<'a> {
+        JsonObject::new(self.0)
+    }
+
+    pub(crate) fn array(self) -> JsonArray<'a> {
+        self.0.push('[');
+        JsonArray {
+            buf: self.0,
+            empty: true,
+        }
+    }
+}
+
+/// Writes the fields of an event as entries of an object, like the JSON formatter of
+/// `tracing_subscriber`.
+pub(crate) struct JsonVisitor<'a, 'b>(pub(crate) &'a mut JsonObject<'b>);
+
+impl Visit for JsonVisitor<'_, '_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.key(field.name()).f64(value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.key(field.name()).i64(value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.key(field.name()).u64(value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.key(field.name()).bool(value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.key(field.name()).str(value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.key(field.name()).debug(value);
+    }
+}
+
+fn needs_escape(byte: u8) -> bool {
+    byte < 0x20 || byte == b'"' || byte == b'\\'
+}
+
+// escapes like `serde_json`, copying the runs of characters needing no escaping at once
+fn escape(buf: &mut String, value: &str) {
+    let mut start = 0;
+    for (i, byte) in value.bytes().enumerate() {
+        if !needs_escape(byte) {
+            continue;
+        }
+        buf.push_str(&value[start..i]);
+        match byte {
+            b'"' => buf.push_str("\\\""),
+            b'\\' => buf.push_str("\\\\"),
+            b'\n' => buf.push_str("\\n"),
+            b'\r' => buf.push_str("\\r"),
+            b'\t' => buf.push_str("\\t"),
+            0x08 => buf.push_str("\\b"),
+            0x0c => buf.push_str("\\f"),
+            _ => {
+                const HEX: &[u8; 16] = b"0123456789abcdef";
+                buf.push_str("\\u00");
+                buf.push(HEX[(byte >> 4) as usize] as char);
+                buf.push(HEX[(byte & 0xf) as usize] as char);
+            }
+        }
+        start = i + 1;
+    }
+    buf.push_str(&value[start..]);
+}
+
+/// Escapes the strings written to it.
+struct Escaper<'a>(&'a mut String);
+
+impl Write for Escaper<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        escape(self.0, s);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_matches_serde_json() {
+        let value = json!({
+            "message": "quote \" backslash \\ newline \n tab \t bell \u{7} unicode é",
+            "numbers": [1, -2, 1.5, 1e300],
+            "nested": {"ok": true, "none": null},
+        });
+        let mut buf = String::new();
+        JsonValue(&mut buf).value(&value);
+        assert_eq!(buf, serde_json::to_string(&value).unwrap());
+
+        let mut buf = String::new();
+        let mut object = JsonObject::new(&mut buf);
+        object.static_key("float").f64(1.0);
+        object.static_key("nan").f64(f64::NAN);
+        object.str_with("empty", |_| Ok(())).unwrap();
+        object.key("debug \"key\"").debug(&Some("value"));
+        object.str_with("time", |w| write!(w, "now")).unwrap();
+        object.end();
+        assert_eq!(
+            buf,
+            r#"{"float":1.0,"nan":null,"debug \"key\"":"Some(\"value\")","time":"now"}"#
+        );
+    }
+}
@@ -0,0 +1,233 @@
+//! Datadog native layout of log records.
+//!
+//! Datadog reserves some attributes for logs, which it otherwise needs remappers in the log
+//! pipeline to find: `status`, `message`, `logger.name`, `host`, `service`, `ddsource` and the
+//! `error.kind`, `error.message` and `error.stack` of the error tracking.
+use std::error::Error;
+use std::fmt::Debug;
+
+use tracing::field::{Field, Visit};
+use tracing::Level;
+
+use super::json::{JsonObject, JsonValue};
+
+/// Layout of the log records written by the [`super::DatadogFormatter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LogLayout {
+    /// The `tracing_subscriber` JSON layout: `level`, `target` and the event fields.
+    #[default]
+    Tracing,
+    /// The Datadog reserved attributes: `status`, `logger.name`, `logger.thread_name`,
+    /// `message`, `host`, `service` and `ddsource`, and `error.message` and `error.stack` for
+    /// events with an `error` field or an error value, and `error.kind` for those with an
+    /// `error.kind` field.
+    Datadog,
+}
+
+impl LogLayout {
+    /// Keys written by the formatter, never overridden by event or span fields.
+    pub(crate) fn reserved_keys(self) -> &'static [&'static str] {
+        match self {
+            LogLayout::Tracing => &[
+                "timestamp",
+                "level",
+                "target",
+                "filename",
+                "line_number",
+                "module_path",
+                "threadName",
+                "threadId",
+                "dd.span_id",
+                "dd.trace_id",
+            ],
+            LogLayout::Datadog => &[
+                "timestamp",
+                "status",
+                "message",
+                "logger",
+                "host",
+                "service",
+                "ddsource",
+                "error",
+                "dd.span_id",
+                "dd.trace_id",
+            ],
+        }
+    }
+}
+
+/// Status of the record, as understood by the Datadog status remapper.
+pub(crate) fn status(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "error",
+        Level::WARN => "warn",
+        Level::INFO => "info",
+        Level::DEBUG => "debug",
+        Level::TRACE => "trace",
+    }
+}
+
+/// The error of an event. Its kind is only known from an explicit `error.kind` field, as the
+/// name of an error type is not available at runtime.
+#[derive(Debug, Default)]
+pub(crate) struct ErrorInfo {
+    kind: Option<String>,
+    message: Option<String>,
+    stack: Option<String>,
+}
+
+impl ErrorInfo {
+    pub(crate) fn write(&self, value: JsonValue<'_>) {
+        let mut object = value.object();
+        if let Some(kind) = &self.kind {
+            object.static_key("kind").str(kind);
+        }
+        if let Some(message) = &self.message {
+            object.static_key("message").str(message);
+        }
+        if let Some(stack) = &self.stack {
+            object.static_key("stack").str(stack);
+        }
+        object.end();
+    }
+
+    fn set_error(&mut self, error: &(dyn Error + 'static)) {
+        // errors don't carry stack traces, the chain of their sources is used instead
+        let mut stack = error.to_string();
+        let mut source = error.source();
+        while let Some(cause) = source {
+            stack.push_str("\ncaused by: ");
+            stack.push_str(&cause.to_string());
+            source = cause.source();
+        }
+        self.message = Some(error.to_string());
+        self.stack = error.source().is_some().then_some(stack);
+    }
+}
+
+/// Writes the fields of an event as entries of an object, skipping the reserved keys, and takes
+/// the error out of the `error` field or error values.
+pub(crate) struct FieldVisitor<'a, 'b> {
+    object: &'a mut JsonObject<'b>,
+    reserved_keys: &'static [&'static str],
+    pub(crate) error: Option<ErrorInfo>,
+}
+
+impl<'a, 'b> FieldVisitor<'a, 'b> {
+    pub(crate) fn new(object: &'a mut JsonObject<'b>, layout: LogLayout) -> Self {
+        FieldVisitor {
+            object,
+            reserved_keys: layout.reserved_keys(),
+            error: None,
+        }
+    }
+
+    // the entry of a field, `None` for the `error` and `error.kind` fields and the other reserved
+    // keys
+    fn entry(&mut self, field: &Field, value: impl FnOnce() -> String) -> Option<JsonValue<'_>> {
+        let name = field.name();
+        if name == "error" {
+            let error = self.error.get_or_insert_with(ErrorInfo::default);
+            if error.message.is_none() {
+                error.message = Some(value());
+            }
+            None
+        } else if name == "error.kind" {
+            self.error.get_or_insert_with(ErrorInfo::default).kind = Some(value());
+            None
+        } else if name != "message" && self.reserved_keys.contains(&name) {
+            None
+        } else {
+            Some(self.object.key(name))
+        }
+    }
+}
+
+impl Visit for FieldVisitor<'_, '_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if let Some(entry) = self.entry(field, || value.to_string()) {
+            entry.f64(value);
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        if let Some(entry) = self.entry(field, || value.to_string()) {
+            entry.i64(value);
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if let Some(entry) = self.entry(field, || value.to_string()) {
+            entry.u64(value);
+        }
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        if let Some(entry) = self.entry(field, || value.to_string()) {
+            entry.bool(value);
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Some(entry) = self.entry(field, || value.to_string()) {
+            entry.str(value);
+        }
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn Error + 'static)) {
+        let name = field.name();
+        if name == "message" || !self.reserved_keys.contains(&name) {
+            self.object.key(name).display(value);
+        }
+        let error = self.error.get_or_insert_with(ErrorInfo::default);
+        if error.message.is_none() {
+            error.set_error(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if let Some(entry) = self.entry(field, || format!("{value:?}")) {
+            entry.debug(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct QueryError {
+        source: std::io::Error,
+    }
+
+    impl std::fmt::Display for QueryError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "query failed")
+        }
+    }
+
+    impl Error for QueryError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.source)
+        }
+    }
+
+    #[test]
+    fn test_error_info() {
+        let error = QueryError {
+            source: std::io::Error::other("connection reset"),
+        };
+
+        let mut info = ErrorInfo::default();
+        info.set_error(&error);
+
+        assert_eq!(info.kind, None);
+        assert_eq!(info.message.as_deref(), Some("query failed"));
+        assert_eq!(
+            info.stack.as_deref(),
+            Some("query failed\ncaused by: connection reset")
+        );
+    }
+}
@@ -0,0 +1,672 @@
+//! An event formatter to emit events in a way that Datadog can correlate them with traces.
+//!
+//! Datadog's trace ID and span ID format is different from the OpenTelemetry standard.
+//! Using this formatter, the trace ID is converted to the correct format.
+//! It also adds the trace ID to the `dd.trace_id` field and the span ID to the
+//! `dd.span_id` field, which is where Datadog looks for these by default
+//! (although the path to the trace ID can be overridden in Datadog).
+//!
+//! Like the JSON formatter of `tracing_subscriber`, the fields of the current span and the list
+//! of its parents can also be added, see [`DatadogFormatter::with_current_span`] and
+//! [`DatadogFormatter::with_span_list`]. Span fields are read from their JSON representation,
+//! so the layer must use the JSON fields formatter, as set by `fmt::layer().json()`.
+//!
+//! Records use the `tracing_subscriber` JSON layout by default, or the attributes reserved by
+//! Datadog with [`LogLayout::Datadog`].
+//!
+//! The [`LogFormat::Pretty`], [`LogFormat::Compact`] and [`LogFormat::Full`] formats are
+//! human-readable alternatives for local development, see [`CorrelatedFormat`].
+//!
+//! Records are written as JSON directly in a buffer reused by the events of a thread, instead of
+//! going through `serde_json`, see `benches/formatter.rs`. Only the `error` field of the
+//! [`LogLayout::Datadog`] layout is buffered, and the span fields are copied from their JSON
+//! representation, except with [`DatadogFormatter::flatten_current_span`] which parses them.
+
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use serde_json::{Map, Value};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OtelData;
+
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+mod json;
+mod layout;
+mod text;
+mod timer;
+
+use json::{JsonObject, JsonValue, JsonVisitor};
+use layout::FieldVisitor;
+pub use layout::LogLayout;
+pub use text::CorrelatedFormat;
+pub use timer::Timer;
+
+/// Format of the logs written by [`crate::init()`], set with the `LOG_FORMAT` environment
+/// variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LogFormat {
+    /// JSON records written by the [`DatadogFormatter`]
+    #[default]
+    Json,
+    /// The multi-line `tracing_subscriber` pretty format
+    Pretty,
+    /// The `tracing_subscriber` compact format
+    Compact,
+    /// The default `tracing_subscriber` format
+    Full,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "full" => Ok(LogFormat::Full),
+            _ => Err(format!("unsupported log format {s}")),
+        }
+    }
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogFormat::Json => write!(f, "json"),
+            LogFormat::Pretty => write!(f, "pretty"),
+            LogFormat::Compact => write!(f, "compact"),
+            LogFormat::Full => write!(f, "full"),
+        }
+    }
+}
+
+struct DatadogId(u64);
+
+struct TraceInfo {
+    trace_id: DatadogId,
+    span_id: DatadogId,
+}
+
+impl From<TraceId> for DatadogId {
+    fn from(value: TraceId) -> Self {
+        let bytes = &value.to_bytes()[std::mem::size_of::<u64>()..std::mem::size_of::<u128>()];
+        Self(u64::from_be_bytes(bytes.try_into().unwrap_or_default()))
+    }
+}
+
+impl From<SpanId> for DatadogId {
+    fn from(value: SpanId) -> Self {
+        Self(u64::from_be_bytes(value.to_bytes()))
+    }
+}
+
+fn lookup_trace_info<S>(span_ref: &SpanRef<S>) -> Option<TraceInfo>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    span_ref.extensions().get::<OtelData>().map(|o| {
+        let trace_id = if o.parent_cx.has_active_span() {
+            o.parent_cx.span().span_context().trace_id()
+        } else {
+            o.builder.trace_id.unwrap_or(TraceId::INVALID)
+        };
+        TraceInfo {
+            trace_id: trace_id.into(),
+            span_id: o.builder.span_id.unwrap_or(SpanId::INVALID).into(),
+        }
+    })
+}
+
+// name and recorded fields of a span, whose JSON representation is copied without parsing it
+fn write_span<S, N>(value: JsonValue<'_>, span_ref: &SpanRef<S>)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    let extensions = span_ref.extensions();
+    let fields = extensions
+        .get::<FormattedFields<N>>()
+        .and_then(|fields| fields.strip_prefix('{')?.strip_suffix('}'))
+        .filter(|fields| !fields.trim().is_empty());
+    let mut object = value.object();
+    object.static_key("name").str(span_ref.name());
+    if let Some(fields) = fields {
+        object.raw_entries(fields);
+    }
+    object.end();
+}
+
+// recorded fields of a span, parsed to flatten them
+fn span_fields<S, N>(span_ref: &SpanRef<S>) -> Map<String, Value>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    span_ref
+        .extensions()
+        .get::<FormattedFields<N>>()
+        .and_then(|fields| serde_json::from_str(fields).ok())
+        .unwrap_or_default()
+}
+
+// mostly stolen from here: https://github.com/tokio-rs/tracing/issues/1531
+/// Formats events as JSON objects with the Datadog trace and span IDs.
+///
+/// ```
+/// use datadog_tracing::formatter::DatadogFormatter;
+///
+/// let layer = tracing_subscriber::fmt::layer::<tracing_subscriber::Registry>()
+///     .json()
+///     .event_format(DatadogFormatter::default().with_current_span(true).flatten_current_span(true));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DatadogFormatter {
+    layout: LogLayout,
+    timer: Timer,
+    service: Option<String>,
+    host: Option<String>,
+    display_current_span: bool,
+    flatten_current_span: bool,
+    display_span_list: bool,
+    display_file: bool,
+    display_line_number: bool,
+    display_module_path: bool,
+    display_thread_names: bool,
+    display_thread_ids: bool,
+    omit_trace_ids: bool,
+}
+
+impl DatadogFormatter {
+    /// Sets the layout of the records, [`LogLayout::Tracing`] by default.
+    ///
+    /// With [`LogLayout::Datadog`], the `service` and `host` attributes are read from the
+    /// `DD_SERVICE`, and `DD_HOSTNAME` or `HOSTNAME`, environment variables unless set.
+    pub fn with_layout(mut self, layout: LogLayout) -> Self {
+        self.layout = layout;
+        if layout == LogLayout::Datadog {
+            self.service = self.service.or_else(|| std::env::var("DD_SERVICE").ok());
+            self.host = self.host.or_else(|| {
+                std::env::var("DD_HOSTNAME")
+                    .or_else(|_| std::env::var("HOSTNAME"))
+                    .ok()
+            });
+        }
+        self
+    }
+
+    /// Sets the format and clock of the `timestamp`, RFC 3339 by default.
+    ///
+    /// ```
+    /// use datadog_tracing::formatter::{DatadogFormatter, Timer};
+    ///
+    /// let formatter = DatadogFormatter::default().with_timer(Timer::EpochMillis);
+    /// ```
+    pub fn with_timer(mut self, timer: Timer) -> Self {
+        self.timer = timer;
+        self
+    }
+
+    /// Sets the `service` attribute of the [`LogLayout::Datadog`] layout.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Sets the `host` attribute of the [`LogLayout::Datadog`] layout.
+    pub fn with_host<T: Into<String>>(mut self, host: T) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Adds the name and fields of the current span under `span`, `false` by default.
+    pub fn with_current_span(mut self, display_current_span: bool) -> Self {
+        self.display_current_span = display_current_span;
+        self
+    }
+
+    /// Writes the fields of the current span at the top level instead of under `span`, so
+    /// they can be used as Datadog facets, `false` by default. Event fields take precedence.
+    pub fn flatten_current_span(mut self, flatten_current_span: bool) -> Self {
+        self.flatten_current_span = flatten_current_span;
+        self
+    }
+
+    /// Adds the names and fields of the current span and its parents under `spans`, from the
+    /// root to the current span, `false` by default.
+    pub fn with_span_list(mut self, display_span_list: bool) -> Self {
+        self.display_span_list = display_span_list;
+        self
+    }
+
+    /// Adds the source file of the event, as `filename`, or `logger.file` with the
+    /// [`LogLayout::Datadog`] layout, `false` by default.
+    pub fn with_file(mut self, display_file: bool) -> Self {
+        self.display_file = display_file;
+        self
+    }
+
+    /// Adds the source line of the event, as `line_number`, or `logger.line` with the
+    /// [`LogLayout::Datadog`] layout, `false` by default.
+    pub fn with_line_number(mut self, display_line_number: bool) -> Self {
+        self.display_line_number = display_line_number;
+        self
+    }
+
+    /// Adds the module path of the event, as `module_path`, or `logger.method_name` with the
+    /// [`LogLayout::Datadog`] layout, `false` by default.
+    pub fn with_module_path(mut self, display_module_path: bool) -> Self {
+        self.display_module_path = display_module_path;
+        self
+    }
+
+    /// Adds the name of the thread emitting the event as `threadName`, `false` by default.
+    ///
+    /// The [`LogLayout::Datadog`] layout always has the `logger.thread_name` attribute.
+    pub fn with_thread_names(mut self, display_thread_names: bool) -> Self {
+        self.display_thread_names = display_thread_names;
+        self
+    }
+
+    /// Adds the ID of the thread emitting the event, as `threadId`, or `logger.thread_id` with
+    /// the [`LogLayout::Datadog`] layout, `false` by default.
+    pub fn with_thread_ids(mut self, display_thread_ids: bool) -> Self {
+        self.display_thread_ids = display_thread_ids;
+        self
+    }
+
+    /// Adds the `dd.trace_id` and `dd.span_id` of the current span, `true` by default.
+    pub fn with_trace_ids(mut self, display_trace_ids: bool) -> Self {
+        self.omit_trace_ids = !display_trace_ids;
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for DatadogFormatter
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        BUFFER.with(|buffer| match buffer.try_borrow_mut() {
+            Ok(mut buf) => {
+                buf.clear();
+                self.write_event(ctx, &mut buf, event)?;
+                let result = writer.write_str(&buf);
+                if buf.capacity() > MAX_RETAINED_CAPACITY {
+                    *buf = String::new();
+                }
+                result
+            }
+            // an event logged while formatting another one, e.g. by a `Debug` implementation
+            Err(_) => {
+                let mut buf = String::new();
+                self.write_event(ctx, &mut buf, event)?;
+                writer.write_str(&buf)
+            }
+        })
+    }
+}
+
+thread_local! {
+    // reused by the events of a thread, so that formatting them doesn't allocate
+    static BUFFER: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+// buffers grown by unusually large events are not kept
+const MAX_RETAINED_CAPACITY: usize = 64 * 1024;
+
+impl DatadogFormatter {
+    fn write_event<S, N>(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        buf: &mut String,
+        event: &Event<'_>,
+    ) -> std::fmt::Result
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'writer> FormatFields<'writer> + 'static,
+    {
+        let meta = event.metadata();
+        let mut object = JsonObject::new(buf);
+        self.timer.write(&mut object)?;
+        match self.layout {
+            LogLayout::Tracing => {
+                object.static_key("level").str(meta.level().as_str());
+                object.static_key("target").str(meta.target());
+                if let Some(file) = meta.file().filter(|_| self.display_file) {
+                    object.static_key("filename").str(file);
+                }
+                if let Some(line) = meta.line().filter(|_| self.display_line_number) {
+                    object.static_key("line_number").u64(line.into());
+                }
+                if let Some(path) = meta.module_path().filter(|_| self.display_module_path) {
+                    object.static_key("module_path").str(path);
+                }
+                let thread = std::thread::current();
+                if let Some(name) = thread.name().filter(|_| self.display_thread_names) {
+                    object.static_key("threadName").str(name);
+                }
+                if self.display_thread_ids {
+                    object.static_key("threadId").debug(&thread.id());
+                }
+                event.record(&mut JsonVisitor(&mut object));
+            }
+            LogLayout::Datadog => {
+                object
+                    .static_key("status")
+                    .str(layout::status(meta.level()));
+                let mut visitor = FieldVisitor::new(&mut object, self.layout);
+                event.record(&mut visitor);
+                let error = visitor.error;
+
+                let mut logger = object.static_key("logger").object();
+                logger.static_key("name").str(meta.target());
+                let thread = std::thread::current();
+                if let Some(thread_name) = thread.name() {
+                    logger.static_key("thread_name").str(thread_name);
+                }
+                if self.display_thread_ids {
+                    logger.static_key("thread_id").debug(&thread.id());
+                }
+                if let Some(file) = meta.file().filter(|_| self.display_file) {
+                    logger.static_key("file").str(file);
+                }
+                if let Some(line) = meta.line().filter(|_| self.display_line_number) {
+                    logger.static_key("line").u64(line.into());
+                }
+                if let Some(path) = meta.module_path().filter(|_| self.display_module_path) {
+                    logger.static_key("method_name").str(path);
+                }
+                logger.end();
+                if let Some(host) = &self.host {
+                    object.static_key("host").str(host);
+                }
+                if let Some(service) = &self.service {
+                    object.static_key("service").str(service);
+                }
+                object.static_key("ddsource").str("rust");
+                if let Some(error) = &error {
+                    error.write(object.static_key("error"));
+                }
+            }
+        }
+
+        let current_span = ctx.lookup_current();
+        if let Some(span_ref) = current_span.as_ref().filter(|_| self.display_current_span) {
+            if self.flatten_current_span {
+                for (key, value) in span_fields::<S, N>(span_ref) {
+                    if !self.layout.reserved_keys().contains(&key.as_str())
+                        && event.fields().all(|field| field.name() != key)
+                    {
+                        object.key(&key).value(&value);
+                    }
+                }
+            } else {
+                write_span::<S, N>(object.static_key("span"), span_ref);
+            }
+        }
+        if self.display_span_list {
+            if let Some(scope) = ctx.event_scope() {
+                let mut spans = object.static_key("spans").array();
+                for span_ref in scope.from_root() {
+                    write_span::<S, N>(spans.element(), &span_ref);
+                }
+                spans.end();
+            }
+        }
+
+        if let Some(span_ref) = current_span.as_ref().filter(|_| !self.omit_trace_ids) {
+            if let Some(trace_info) = lookup_trace_info(span_ref) {
+                object.static_key("dd.span_id").u64(trace_info.span_id.0);
+                object.static_key("dd.trace_id").u64(trace_info.trace_id.0);
+            }
+        }
+
+        object.end();
+        buf.push('\n');
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::SecondsFormat;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::time::FormatTime;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // formats the events logged by `f`, returning the last one
+    fn format(formatter: DatadogFormatter, f: impl FnOnce()) -> Value {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .event_format(formatter)
+            .with_writer(move || writer.clone())
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        serde_json::from_str(output.lines().last().unwrap()).unwrap()
+    }
+
+    fn log_in_spans() {
+        let _parent = tracing::info_span!("handle_request", request_id = "abc").entered();
+        let _child =
+            tracing::info_span!("load_user", user_id = 42, tenant = "acme", level = "high")
+                .entered();
+        tracing::info!(user_id = 7, "loaded");
+    }
+
+    #[test]
+    fn test_span_fields_are_not_included_by_default() {
+        let log = format(DatadogFormatter::default(), log_in_spans);
+
+        assert_eq!(log["message"], "loaded");
+        assert_eq!(log["user_id"], 7);
+        assert!(log.get("span").is_none());
+        assert!(log.get("spans").is_none());
+    }
+
+    #[test]
+    fn test_current_span_and_span_list() {
+        let formatter = DatadogFormatter::default()
+            .with_current_span(true)
+            .with_span_list(true);
+        let log = format(formatter, log_in_spans);
+
+        assert_eq!(log["span"]["name"], "load_user");
+        assert_eq!(log["span"]["user_id"], 42);
+        assert_eq!(log["spans"][0]["name"], "handle_request");
+        assert_eq!(log["spans"][0]["request_id"], "abc");
+        assert_eq!(log["spans"][1]["name"], "load_user");
+    }
+
+    #[test]
+    fn test_datadog_layout() {
+        let formatter = DatadogFormatter::default()
+            .with_service("my-service")
+            .with_host("my-host")
+            .with_layout(LogLayout::Datadog);
+        let error = "12a".parse::<u8>().unwrap_err();
+        let log = format(formatter, || {
+            tracing::warn!(
+                error = &error as &dyn std::error::Error,
+                user_id = 7,
+                service = "overridden",
+                "invalid id"
+            )
+        });
+
+        assert_eq!(log["status"], "warn");
+        assert_eq!(log["message"], "invalid id");
+        assert_eq!(log["logger"]["name"], "datadog_tracing::formatter::tests");
+        assert_eq!(log["host"], "my-host");
+        assert_eq!(log["service"], "my-service");
+        assert_eq!(log["ddsource"], "rust");
+        // the kind of an error is unknown without an explicit field
+        assert!(log["error"].get("kind").is_none());
+        assert_eq!(log["error"]["message"], "invalid digit found in string");
+        assert_eq!(log["user_id"], 7);
+        assert!(log.get("level").is_none());
+
+        let formatter = DatadogFormatter::default().with_layout(LogLayout::Datadog);
+        let log = format(formatter, || {
+            tracing::error!(
+                error.kind = "ParseIntError",
+                error = &error as &dyn std::error::Error,
+                "invalid id"
+            )
+        });
+        assert_eq!(log["error"]["kind"], "ParseIntError");
+        assert_eq!(log["error"]["message"], "invalid digit found in string");
+        assert!(log.get("error.kind").is_none());
+    }
+
+    #[test]
+    fn test_source_location_and_thread() {
+        let with_metadata = |layout| {
+            DatadogFormatter::default()
+                .with_layout(layout)
+                .with_file(true)
+                .with_line_number(true)
+                .with_module_path(true)
+                .with_thread_names(true)
+                .with_thread_ids(true)
+        };
+        let thread_name = std::thread::current().name().map(str::to_string);
+
+        let log = format(with_metadata(LogLayout::Tracing), || tracing::info!("done"));
+        assert_eq!(log["filename"], file!());
+        assert!(log["line_number"].as_u64().is_some());
+        assert_eq!(log["module_path"], module_path!());
+        assert_eq!(log["threadName"].as_str(), thread_name.as_deref());
+        assert!(log["threadId"].as_str().unwrap().starts_with("ThreadId("));
+
+        let log = format(with_metadata(LogLayout::Datadog), || tracing::info!("done"));
+        assert_eq!(log["logger"]["file"], file!());
+        assert!(log["logger"]["line"].as_u64().is_some());
+        assert_eq!(log["logger"]["method_name"], module_path!());
+        assert!(log["logger"]["thread_id"].is_string());
+        assert!(log.get("filename").is_none());
+    }
+
+    #[test]
+    fn test_event_logged_while_formatting() {
+        struct Noisy;
+
+        impl std::fmt::Debug for Noisy {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                tracing::info!("formatting");
+                write!(f, "noisy")
+            }
+        }
+
+        let log = format(DatadogFormatter::default(), || {
+            tracing::info!(value = ?Noisy, "done");
+            tracing::info!(escaped = "\"quoted\"\n", "done");
+        });
+        assert_eq!(log["escaped"], "\"quoted\"\n");
+    }
+
+    #[test]
+    fn test_timer() {
+        struct FixedTime;
+
+        impl FormatTime for FixedTime {
+            fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+                write!(w, "2024-01-01T00:00:00Z")
+            }
+        }
+
+        let log = format(DatadogFormatter::default(), || tracing::info!("done"));
+        let timestamp = log["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+
+        let formatter =
+            DatadogFormatter::default().with_timer(Timer::Rfc3339(SecondsFormat::Millis));
+        let log = format(formatter, || tracing::info!("done"));
+        // e.g. 2024-01-01T00:00:00.123+00:00
+        assert_eq!(log["timestamp"].as_str().unwrap().len(), 29);
+
+        let formatter = DatadogFormatter::default().with_timer(Timer::EpochMillis);
+        let log = format(formatter, || tracing::info!("done"));
+        assert!(log["timestamp"].as_u64().unwrap() > 1_700_000_000_000);
+
+        let formatter = DatadogFormatter::default().with_timer(Timer::custom(FixedTime));
+        let log = format(formatter, || tracing::info!("done"));
+        assert_eq!(log["timestamp"], "2024-01-01T00:00:00Z");
+
+        let formatter = DatadogFormatter::default().with_timer(Timer::custom(()));
+        let log = format(formatter, || tracing::info!("done"));
+        assert!(log.get("timestamp").is_none());
+    }
+
+    #[test]
+    fn test_flatten_current_span() {
+        let formatter = DatadogFormatter::default()
+            .with_current_span(true)
+            .flatten_current_span(true);
+        let log = format(formatter, log_in_spans);
+
+        // event fields and the formatter keys take precedence
+        assert_eq!(log["user_id"], 7);
+        assert_eq!(log["level"], "INFO");
+        assert_eq!(log["tenant"], "acme");
+        assert!(log.get("span").is_none());
+        assert!(log.get("request_id").is_none());
+    }
+
+    #[test]
+    fn test_trace_id_converted_to_datadog_id() {
+        let trace_id = TraceId::from_hex("2de7888d8f42abc9c7ba048b78f7a9fb").unwrap();
+        let datadog_id: DatadogId = trace_id.into();
+
+        assert_eq!(datadog_id.0, 14391820556292303355);
+    }
+
+    #[test]
+    fn test_invalid_trace_id_converted_to_zero() {
+        let trace_id = TraceId::INVALID;
+        let datadog_id: DatadogId = trace_id.into();
+
+        assert_eq!(datadog_id.0, 0);
+    }
+
+    #[test]
+    fn test_span_id_converted_to_datadog_id() {
+        let span_id = SpanId::from_hex("58406520a0066491").unwrap();
+        let datadog_id: DatadogId = span_id.into();
+
+        assert_eq!(datadog_id.0, 6359193864645272721);
+    }
+}
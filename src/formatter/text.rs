@@ -0,0 +1,157 @@
+//! Human-readable formats with the Datadog trace and span IDs.
+use std::fmt;
+
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::{Format, Full, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+use super::lookup_trace_info;
+
+const DIMMED: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// Wraps a `tracing_subscriber` text format, e.g. `Format::default().compact()`, appending the
+/// `dd.trace_id` and `dd.span_id` of the current span to its records.
+///
+/// They are appended to the line of single-line formats, and on their own line with the
+/// multi-line pretty format.
+///
+/// ```
+/// use datadog_tracing::formatter::CorrelatedFormat;
+/// use tracing_subscriber::fmt::format::Format;
+///
+/// let layer = tracing_subscriber::fmt::layer::<tracing_subscriber::Registry>()
+///     .compact()
+///     .event_format(CorrelatedFormat::new(Format::default().compact()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CorrelatedFormat<F = Full> {
+    plain: Format<F>,
+    ansi: Format<F>,
+}
+
+impl<F: Clone> CorrelatedFormat<F> {
+    /// Wraps `format`, whose colors follow the ones of the layer.
+    pub fn new(format: Format<F>) -> Self {
+        CorrelatedFormat {
+            plain: format.clone().with_ansi(false),
+            ansi: format.with_ansi(true),
+        }
+    }
+}
+
+impl<S, N, F> FormatEvent<S, N> for CorrelatedFormat<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+    Format<F>: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let ansi = writer.has_ansi_escapes();
+        let format = if ansi { &self.ansi } else { &self.plain };
+        let Some(trace_info) = ctx
+            .lookup_current()
+            .and_then(|span_ref| lookup_trace_info(&span_ref))
+        else {
+            return format.format_event(ctx, writer, event);
+        };
+
+        // the record is formatted first to insert the IDs before its trailing newlines
+        let mut record = String::new();
+        format.format_event(ctx, Writer::new(&mut record), event)?;
+        let line = record.trim_end_matches('\n');
+        let separator = if line.contains('\n') { "\n    " } else { " " };
+        let (dimmed, reset) = if ansi { (DIMMED, RESET) } else { ("", "") };
+        write!(
+            writer,
+            "{line}{separator}{dimmed}dd.trace_id={} dd.span_id={}{reset}{}",
+            trace_info.trace_id.0,
+            trace_info.span_id.0,
+            &record[line.len()..]
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::trace::Tracer;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::layer::{Layered, SubscriberExt};
+    use tracing_subscriber::{Layer, Registry};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    type Subscriber = Layered<OpenTelemetryLayer<Registry, Tracer>, Registry>;
+
+    // formats the events logged in and out of a span with the layer writing to the buffer
+    fn format(layer: impl FnOnce(Buffer) -> Box<dyn Layer<Subscriber> + Send + Sync>) -> String {
+        let buffer = Buffer::default();
+        let provider = TracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")))
+            .with(layer(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("outside");
+            let _span = tracing::info_span!("handle_request").entered();
+            tracing::info!(user_id = 42, "inside");
+        });
+
+        let output = buffer.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_correlated_format() {
+        let output = format(|buffer| {
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .event_format(CorrelatedFormat::new(Format::default().compact()))
+                .with_writer(move || buffer.clone())
+                .boxed()
+        });
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("outside"), "{output}");
+        assert!(!lines[0].contains("dd.trace_id"), "{output}");
+        let (record, ids) = lines[1].split_once(" dd.trace_id=").unwrap();
+        assert!(record.ends_with("user_id=42"), "{output}");
+        let (trace_id, span_id) = ids.split_once(" dd.span_id=").unwrap();
+        assert!(trace_id.parse::<u64>().unwrap() > 0);
+        assert!(span_id.parse::<u64>().unwrap() > 0);
+
+        let output = format(|buffer| {
+            tracing_subscriber::fmt::layer()
+                .pretty()
+                .with_ansi(false)
+                .event_format(CorrelatedFormat::new(Format::default().pretty()))
+                .with_writer(move || buffer.clone())
+                .boxed()
+        });
+        assert!(output
+            .lines()
+            .any(|line| line.starts_with("    dd.trace_id=")));
+        assert!(output.ends_with('\n'));
+    }
+}
@@ -0,0 +1,114 @@
+//! Timestamps of the log records.
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use chrono::{SecondsFormat, Utc};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+
+use super::json::JsonObject;
+
+/// Format and clock of the `timestamp` of the records written by the
+/// [`super::DatadogFormatter`].
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum Timer {
+    /// RFC 3339 string in UTC with the given precision, e.g. `2024-01-01T12:00:00.123+00:00`
+    Rfc3339(SecondsFormat),
+    /// Milliseconds since the Unix epoch, the fastest format for Datadog to parse
+    EpochMillis,
+    /// Nanoseconds since the Unix epoch
+    EpochNanos,
+    /// Any `tracing_subscriber` timer, e.g. a fixed time for snapshot tests. No `timestamp` is
+    /// written if it writes nothing.
+    Custom(Arc<dyn FormatTime + Send + Sync>),
+}
+
+impl Timer {
+    /// Uses a `tracing_subscriber` timer, see [`Timer::Custom`].
+    pub fn custom<T: FormatTime + Send + Sync + 'static>(timer: T) -> Self {
+        Timer::Custom(Arc::new(timer))
+    }
+
+    pub(crate) fn write(&self, object: &mut JsonObject<'_>) -> fmt::Result {
+        match self {
+            Timer::Rfc3339(format) => {
+                let timestamp = Utc::now();
+                object
+                    .static_key("timestamp")
+                    .display(&timestamp.format(rfc3339_format(*format)));
+            }
+            Timer::EpochMillis => object
+                .static_key("timestamp")
+                .u64(since_epoch().as_millis() as u64),
+            Timer::EpochNanos => object
+                .static_key("timestamp")
+                .u64(since_epoch().as_nanos() as u64),
+            Timer::Custom(timer) => object.str_with("timestamp", |mut w| {
+                timer.format_time(&mut Writer::new(&mut w))
+            })?,
+        }
+        Ok(())
+    }
+}
+
+// formats with the same output as `DateTime::to_rfc3339_opts`, without allocating a `String`
+fn rfc3339_format(format: SecondsFormat) -> &'static str {
+    match format {
+        SecondsFormat::Secs => "%Y-%m-%dT%H:%M:%S%:z",
+        SecondsFormat::Millis => "%Y-%m-%dT%H:%M:%S%.3f%:z",
+        SecondsFormat::Micros => "%Y-%m-%dT%H:%M:%S%.6f%:z",
+        SecondsFormat::Nanos => "%Y-%m-%dT%H:%M:%S%.9f%:z",
+        _ => "%Y-%m-%dT%H:%M:%S%.f%:z",
+    }
+}
+
+fn since_epoch() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// RFC 3339 with the precision fitting the timestamp, as `chrono::DateTime::to_rfc3339`.
+impl Default for Timer {
+    fn default() -> Self {
+        Timer::Rfc3339(SecondsFormat::AutoSi)
+    }
+}
+
+impl Debug for Timer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Timer::Rfc3339(format) => f.debug_tuple("Rfc3339").field(format).finish(),
+            Timer::EpochMillis => write!(f, "EpochMillis"),
+            Timer::EpochNanos => write!(f, "EpochNanos"),
+            Timer::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_rfc3339_format() {
+        for nanos in [0, 120_000_000, 123_456_789] {
+            let timestamp = Utc.timestamp_opt(1_700_000_000, nanos).unwrap();
+            for format in [
+                SecondsFormat::Secs,
+                SecondsFormat::Millis,
+                SecondsFormat::Micros,
+                SecondsFormat::Nanos,
+                SecondsFormat::AutoSi,
+            ] {
+                assert_eq!(
+                    timestamp.format(rfc3339_format(format)).to_string(),
+                    timestamp.to_rfc3339_opts(format, false)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Requests to the Datadog intakes, shared by the agentless exporter and the logs intake writer.
+//!
+//! Payloads are gzip compressed and retried with an exponential backoff on network errors,
+//! `408`, `429` and `5xx` responses, until a deadline.
+use std::fmt::{Display, Formatter};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use opentelemetry_sdk::runtime::{Runtime, Tokio};
+use reqwest::StatusCode;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+// maximum duration of a request, shortened to end before the deadline
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Error of a request to an intake.
+#[derive(Debug)]
+pub(crate) enum PostError {
+    Request(reqwest::Error),
+    /// The deadline passed before the payload could be sent.
+    Timeout,
+}
+
+impl Display for PostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostError::Request(err) => write!(f, "{err}"),
+            PostError::Timeout => write!(f, "request timed out"),
+        }
+    }
+}
+
+impl std::error::Error for PostError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PostError::Request(err) => Some(err),
+            PostError::Timeout => None,
+        }
+    }
+}
+
+/// Gzip compresses the concatenation of `parts`.
+pub(crate) fn gzip(parts: &[&[u8]]) -> io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    for part in parts {
+        encoder.write_all(part)?;
+    }
+    encoder.finish()
+}
+
+/// Posts a gzip compressed `payload` with the requests built by `request`, retrying at most
+/// `max_retries` times as long as the backoff ends before `deadline`.
+///
+/// `on_response` is called after every attempt, with the status of the response if any.
+pub(crate) async fn post_gzip(
+    request: impl Fn() -> reqwest::RequestBuilder,
+    payload: &[u8],
+    max_retries: u32,
+    deadline: Instant,
+    mut on_response: impl FnMut(Option<StatusCode>),
+) -> Result<(), PostError> {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(PostError::Timeout);
+        }
+        let result = request()
+            .timeout(remaining.min(REQUEST_TIMEOUT))
+            .header(reqwest::header::CONTENT_ENCODING, "gzip")
+            .body(payload.to_vec())
+            .send()
+            .await;
+
+        let status = result.as_ref().ok().map(reqwest::Response::status);
+        on_response(status);
+        let retryable = status.is_none_or(|status| {
+            status.is_server_error()
+                || status == StatusCode::TOO_MANY_REQUESTS
+                || status == StatusCode::REQUEST_TIMEOUT
+        });
+        if !retryable || attempt >= max_retries || Instant::now() + backoff >= deadline {
+            result
+                .and_then(|response| response.error_for_status())
+                .map_err(PostError::Request)?;
+            return Ok(());
+        }
+
+        attempt += 1;
+        Tokio.delay(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// A mock intake recording the requests it receives, shared by the tests of the intake clients.
+#[cfg(test)]
+pub(crate) mod mock {
+    use std::convert::Infallible;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+
+    use flate2::read::GzDecoder;
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server, StatusCode};
+
+    /// A request received by the mock intake, with its decompressed body.
+    #[derive(Debug, Clone)]
+    pub(crate) struct MockRequest {
+        pub(crate) uri: hyper::Uri,
+        pub(crate) headers: hyper::HeaderMap,
+        pub(crate) body: Vec<u8>,
+    }
+
+    pub(crate) type Requests = Arc<Mutex<Vec<MockRequest>>>;
+
+    /// Starts a mock intake answering the given statuses in order, then `202 Accepted`, and
+    /// returns the URL of `path`.
+    pub(crate) fn mock_intake(path: &str, statuses: Vec<StatusCode>) -> (String, Requests) {
+        let requests = Requests::default();
+        let statuses = Arc::new(Mutex::new(statuses));
+        let recorded = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let recorded = recorded.clone();
+            let statuses = statuses.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let recorded = recorded.clone();
+                    let statuses = statuses.clone();
+                    async move {
+                        let uri = request.uri().clone();
+                        let headers = request.headers().clone();
+                        let data = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                        let mut body = Vec::new();
+                        GzDecoder::new(data.as_ref())
+                            .read_to_end(&mut body)
+                            .unwrap();
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push(MockRequest { uri, headers, body });
+
+                        let mut statuses = statuses.lock().unwrap();
+                        let status = if statuses.is_empty() {
+                            StatusCode::ACCEPTED
+                        } else {
+                            statuses.remove(0)
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::empty())
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = format!("http://{}{path}", server.local_addr());
+        tokio::spawn(server);
+        (url, requests)
+    }
+}
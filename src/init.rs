@@ -1,21 +1,66 @@
-use crate::formatter::DatadogFormatter;
+use crate::formatter::{CorrelatedFormat, DatadogFormatter, LogFormat};
 use crate::shutdown::TracerShutdown;
-use crate::tracer::build_tracer;
+use crate::startup;
+use crate::telemetry;
+use crate::tracer::{self, TracerBuilder};
+use crate::writer::{DroppedLinesReporter, LogGuard, LogWriter, NonBlockingConfig};
 use opentelemetry::trace::TraceError;
 use std::env;
 use tracing::Subscriber;
-use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::fmt::format::Format;
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{EnvFilter, Layer, Registry};
 
-fn loglevel_filter_layer(dd_enabled: bool) -> EnvFilter {
+/// The components installed by [`init`], read from the environment.
+///
+/// `DD_ENABLED` sets the defaults of the other switches, so that it alone enables the trace
+/// export and JSON logs with the trace and span IDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct InitConfig {
+    /// Exports traces and sends metrics, `DD_TRACE_ENABLED`
+    trace_enabled: bool,
+    /// `LOG_FORMAT`, JSON when `DD_ENABLED` is `true`, the `full` text format otherwise
+    log_format: LogFormat,
+    /// Adds the trace and span IDs to logs, `DD_LOGS_INJECTION`
+    logs_injection: bool,
+}
+
+impl InitConfig {
+    fn from_env() -> Result<Self, TraceError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    // reads the variables with `lookup`, so that tests don't modify the process environment
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, TraceError> {
+        let flag = |key, default| lookup(key).map_or(default, |s| s == "true");
+        let dd_enabled = flag("DD_ENABLED", false);
+        let log_format = match lookup("LOG_FORMAT") {
+            Some(format) => format.parse()?,
+            None if dd_enabled => LogFormat::Json,
+            None => LogFormat::Full,
+        };
+        Ok(InitConfig {
+            trace_enabled: flag("DD_TRACE_ENABLED", dd_enabled),
+            log_format,
+            logs_injection: flag("DD_LOGS_INJECTION", dd_enabled),
+        })
+    }
+
+    // spans are recorded to export them, or only to have IDs to inject in logs
+    fn spans_enabled(&self) -> bool {
+        self.trace_enabled || self.logs_injection
+    }
+}
+
+fn loglevel_filter_layer(spans_enabled: bool) -> EnvFilter {
     let log_level = env::var("RUST_LOG").unwrap_or_else(|_| "info".to_string());
 
     // `axum_tracing_opentelemetry` should be a level info to emit opentelemetry trace & span
     let axum_tracing_log_level = env::var("AXUM_TRACING_LOG_LEVEL").unwrap_or_else(|_| {
-        if dd_enabled {
+        if spans_enabled {
             "trace".to_string()
         } else {
             "off".to_string()
@@ -33,42 +78,199 @@ fn loglevel_filter_layer(dd_enabled: bool) -> EnvFilter {
     EnvFilter::from_default_env()
 }
 
-fn log_layer<S>(
-    dd_enabled: bool,
-    non_blocking: NonBlocking,
+pub(crate) fn log_layer<S, W>(
+    format: LogFormat,
+    logs_injection: bool,
+    ansi: bool,
+    writer: W,
 ) -> Box<dyn Layer<S> + Send + Sync + 'static>
 where
     S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
 {
-    if dd_enabled {
-        Box::new(
-            tracing_subscriber::fmt::layer()
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    // otherwise left to `tracing_subscriber`, which disables colors when `NO_COLOR` is set
+    let layer = if ansi { layer } else { layer.with_ansi(false) };
+    match (format, logs_injection) {
+        (LogFormat::Json, _) => Box::new(
+            layer
                 .json()
-                .event_format(DatadogFormatter)
-                .with_writer(non_blocking),
-        )
-    } else {
-        Box::new(tracing_subscriber::fmt::layer().with_writer(non_blocking))
+                .event_format(DatadogFormatter::default().with_trace_ids(logs_injection)),
+        ),
+        (LogFormat::Pretty, true) => Box::new(
+            layer
+                .pretty()
+                .event_format(CorrelatedFormat::new(Format::default().pretty())),
+        ),
+        (LogFormat::Pretty, false) => Box::new(layer.pretty()),
+        (LogFormat::Compact, true) => {
+            Box::new(layer.event_format(CorrelatedFormat::new(Format::default().compact())))
+        }
+        (LogFormat::Compact, false) => Box::new(layer.compact()),
+        (_, true) => Box::new(layer.event_format(CorrelatedFormat::new(Format::default()))),
+        (_, false) => Box::new(layer),
+    }
+}
+
+/// Installs the global DogStatsD client, and the `metrics` crate recorder with the
+/// `metrics-facade` feature. Errors are reported to the OpenTelemetry error handler by `init`. With the `runtime-metrics` feature, also starts reporting the
+/// Tokio runtime metrics when `DD_RUNTIME_METRICS_ENABLED` is `true`.
+#[cfg(feature = "metrics")]
+fn init_metrics() -> std::io::Result<()> {
+    let client = crate::metrics::DogStatsDBuilder::from_env()?.build()?;
+    #[cfg(feature = "metrics-facade")]
+    let _ = crate::metrics::DogStatsDRecorder::new(client.clone()).install();
+    #[cfg(feature = "runtime-metrics")]
+    if env::var("DD_RUNTIME_METRICS_ENABLED").is_ok_and(|s| s == "true") {
+        let handle = tokio::runtime::Handle::try_current().map_err(std::io::Error::other)?;
+        crate::runtime_metrics::RuntimeMetricsReporter::new(client.clone())
+            .with_handle(handle)
+            .spawn();
     }
+    let _ = crate::metrics::set_global(client);
+    Ok(())
 }
 
-pub fn init() -> Result<(WorkerGuard, TracerShutdown), TraceError> {
-    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stdout());
+/// Starts reporting the tracer stats when `DD_TRACE_HEALTH_METRICS_ENABLED` is `true`, with the
+/// error handler counting the spans dropped by the export queue.
+///
+/// The handler replaces the one of the application, so it is only installed to report stats.
+fn init_telemetry() {
+    if env::var("DD_TRACE_HEALTH_METRICS_ENABLED").is_ok_and(|s| s == "true") {
+        let _ = opentelemetry::global::set_error_handler(telemetry::error_handler);
+        let reporter = telemetry::TracerStatsReporter::new();
+        #[cfg(feature = "metrics")]
+        let reporter = match crate::metrics::global() {
+            Some(client) => reporter.with_dogstatsd(client.clone()),
+            None => reporter,
+        };
+        reporter.spawn();
+    }
+}
+
+/// Installs the global subscriber, with a tracer exporting to Datadog when `DD_TRACE_ENABLED`
+/// is `true`.
+///
+/// The log format and the injection of the trace and span IDs in logs are set independently
+/// with `LOG_FORMAT` and `DD_LOGS_INJECTION`. Without trace export, spans are still recorded
+/// for their IDs to be injected in logs and propagated.
+///
+/// Logs are written to the destination read by [`LogWriter::from_env`], stdout by default,
+/// through a non-blocking writer configured by [`NonBlockingConfig::from_env`].
+pub fn init() -> Result<(LogGuard, TracerShutdown), TraceError> {
+    let writer = LogWriter::from_env().map_err(|err| TraceError::Other(Box::new(err)))?;
+    let non_blocking =
+        NonBlockingConfig::from_env().map_err(|err| TraceError::Other(Box::new(err)))?;
+    init_with_writer(writer, non_blocking)
+}
 
-    let dd_enabled = env::var("DD_ENABLED").map(|s| s == "true").unwrap_or(false);
+/// Same as [`init`], writing logs to `writer` through a non-blocking writer configured by
+/// `non_blocking`, instead of reading them from the environment.
+pub fn init_with_writer(
+    writer: LogWriter,
+    non_blocking: NonBlockingConfig,
+) -> Result<(LogGuard, TracerShutdown), TraceError> {
+    let ansi = writer.ansi();
+    let lossy = non_blocking.is_lossy();
+    let writer = writer
+        .into_writer()
+        .map_err(|err| TraceError::Other(Box::new(err)))?;
+    let (non_blocking, guard, worker) = non_blocking.build(writer);
+    let reporter = lossy.then(|| DroppedLinesReporter::new(&non_blocking).with_worker(worker));
 
-    let tracer = if dd_enabled {
-        Some(build_tracer()?)
+    let config = InitConfig::from_env()?;
+
+    let (tracer, startup) = if config.trace_enabled {
+        let builder = TracerBuilder::from_env()?;
+        let configuration = builder.startup_configuration();
+        let (tracer, discovery) = builder.build_with_discovery()?;
+        (Some(tracer), Some((configuration, discovery)))
+    } else if config.logs_injection {
+        (Some(tracer::build_local_tracer()), None)
     } else {
-        None
+        (None, None)
     };
+    // an unreachable metrics sink doesn't prevent the service from starting
+    #[cfg(feature = "metrics")]
+    if config.trace_enabled {
+        if let Err(err) = init_metrics() {
+            opentelemetry::global::handle_error(opentelemetry::global::Error::Other(format!(
+                "failed to initialize DogStatsD metrics: {err}"
+            )));
+        }
+    }
+    if config.trace_enabled {
+        init_telemetry();
+    }
+
+    // spawned before installing the subscriber, which is left unset when it fails
+    if let Some(reporter) = reporter {
+        #[cfg(feature = "metrics")]
+        let reporter = match crate::metrics::global() {
+            Some(client) => reporter.with_dogstatsd(client.clone()),
+            None => reporter,
+        };
+        reporter
+            .spawn()
+            .map_err(|err| TraceError::Other(Box::new(err)))?;
+    }
+
     let telemetry_layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
 
     Registry::default()
-        .with(loglevel_filter_layer(dd_enabled))
-        .with(log_layer(dd_enabled, non_blocking))
+        .with(loglevel_filter_layer(config.spans_enabled()))
+        .with(log_layer(
+            config.log_format,
+            config.logs_injection,
+            ansi,
+            non_blocking,
+        ))
         .with(telemetry_layer)
         .init();
 
+    // logged once the subscriber is installed
+    if let Some((configuration, discovery)) = startup.filter(|_| startup::startup_logs_enabled()) {
+        startup::log_startup_with_discovery(&configuration, discovery);
+    }
+
     Ok((guard, TracerShutdown {}))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_config_from_lookup() {
+        let with_env = |vars: &[(&str, &str)]| {
+            InitConfig::from_lookup(|key| {
+                vars.iter()
+                    .find(|(name, _)| *name == key)
+                    .map(|(_, value)| value.to_string())
+            })
+            .unwrap()
+        };
+
+        let config = with_env(&[]);
+        assert!(!config.trace_enabled && !config.logs_injection && !config.spans_enabled());
+        assert_eq!(config.log_format, LogFormat::Full);
+
+        let config = with_env(&[("DD_ENABLED", "true")]);
+        assert!(config.trace_enabled && config.logs_injection);
+        assert_eq!(config.log_format, LogFormat::Json);
+
+        // JSON logs with the IDs, without exporting traces
+        let config = with_env(&[("DD_ENABLED", "true"), ("DD_TRACE_ENABLED", "false")]);
+        assert!(!config.trace_enabled && config.logs_injection && config.spans_enabled());
+        assert_eq!(config.log_format, LogFormat::Json);
+
+        // plain logs, exporting traces
+        let config = with_env(&[
+            ("DD_TRACE_ENABLED", "true"),
+            ("LOG_FORMAT", "compact"),
+            ("DD_LOGS_INJECTION", "false"),
+        ]);
+        assert!(config.trace_enabled && !config.logs_injection);
+        assert_eq!(config.log_format, LogFormat::Compact);
+    }
+}
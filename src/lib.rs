@@ -9,12 +9,32 @@
 //! [`axum`]: https://github.com/tokio-rs/axum
 //! [`reqwest`]: https://docs.rs/reqwest/latest/reqwest/
 
+pub mod agent;
 #[cfg(feature = "axum")]
 pub mod axum;
+pub mod exporter;
+#[cfg(test)]
+mod fixtures;
 pub mod formatter;
+#[cfg(any(feature = "agentless", feature = "logs-intake"))]
+mod http;
 pub mod init;
+pub mod mapping;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod processor;
+pub mod propagator;
+#[cfg(feature = "runtime-metrics")]
+pub mod runtime_metrics;
 pub mod shutdown;
+pub mod startup;
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 pub mod tracer;
+pub mod writer;
 
 pub use init::init;
 pub use opentelemetry::global::shutdown_tracer_provider;
@@ -0,0 +1,308 @@
+//! Mapping between OpenTelemetry spans and Datadog span names, resources and types.
+//!
+//! Datadog identifies a span by its operation name (e.g. `http.request`), its resource
+//! (e.g. `GET /users/{id}`) and its type (e.g. `web`). OpenTelemetry spans only have a name,
+//! so these are derived from the span kind and attributes following Datadog conventions:
+//!
+//! | span                           | operation name                  | resource                    | type  |
+//! |--------------------------------|---------------------------------|-----------------------------|-------|
+//! | http server                    | `http.request`                  | `<method> <http.route>`     | `web` |
+//! | http client                    | `http.client.request`           | span name                   | `http`|
+//! | database (`db.system`)         | `<db.system>.query`             | `db.statement` or span name | `sql` |
+//! | messaging (`messaging.system`) | `<messaging.system>.send`       | span name                   |       |
+//! | others                         | instrumentation library name    | span name                   |       |
+//!
+//! Consumer messaging spans use `<messaging.system>.process`, and databases such as `redis`
+//! or `mongodb` use their own span type.
+//!
+//! Explicit `operation.name`, `resource.name` and `span.type` span fields always take precedence.
+//! The operation name and resource mappings can be replaced using
+//! [`crate::tracer::TracerBuilder::with_name_mapping`] and
+//! [`crate::tracer::TracerBuilder::with_resource_mapping`].
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use futures_util::future::BoxFuture;
+use opentelemetry::trace::SpanKind;
+use opentelemetry::{KeyValue, Value};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+
+use crate::telemetry;
+
+/// Span field overriding the Datadog operation name.
+pub const OPERATION_NAME_KEY: &str = "operation.name";
+/// Span field overriding the Datadog resource name.
+pub const RESOURCE_NAME_KEY: &str = "resource.name";
+/// Span field overriding the Datadog span type.
+pub const SPAN_TYPE_KEY: &str = "span.type";
+
+/// Function computing a Datadog field (operation name or resource) from a span.
+pub type SpanMappingFn = dyn for<'a> Fn(&'a SpanData) -> Cow<'a, str> + Send + Sync;
+
+/// Returns the value of a string attribute of the span.
+pub fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a str> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .and_then(|kv| match &kv.value {
+            Value::String(value) => Some(value.as_str()),
+            _ => None,
+        })
+        .filter(|value| !value.is_empty())
+}
+
+fn http_method(span: &SpanData) -> Option<&str> {
+    attribute(span, "http.request.method").or_else(|| attribute(span, "http.method"))
+}
+
+/// Default Datadog operation name, following the conventions of the official tracers.
+pub fn default_name_mapping(span: &SpanData) -> Cow<'_, str> {
+    if let Some(db_system) = attribute(span, "db.system") {
+        return Cow::Owned(format!("{db_system}.query"));
+    }
+    if let Some(messaging_system) = attribute(span, "messaging.system") {
+        let operation = match span.span_kind {
+            SpanKind::Consumer => "process",
+            _ => "send",
+        };
+        return Cow::Owned(format!("{messaging_system}.{operation}"));
+    }
+    match (&span.span_kind, http_method(span)) {
+        (SpanKind::Server, Some(_)) => Cow::Borrowed("http.request"),
+        (SpanKind::Client, Some(_)) => Cow::Borrowed("http.client.request"),
+        _ => Cow::Borrowed(span.instrumentation_lib.name.as_ref()),
+    }
+}
+
+/// Default Datadog resource, following the conventions of the official tracers.
+pub fn default_resource_mapping(span: &SpanData) -> Cow<'_, str> {
+    if attribute(span, "db.system").is_some() {
+        if let Some(statement) = attribute(span, "db.statement") {
+            return Cow::Borrowed(statement);
+        }
+    }
+    if let (SpanKind::Server, Some(method), Some(route)) = (
+        &span.span_kind,
+        http_method(span),
+        attribute(span, "http.route"),
+    ) {
+        return Cow::Owned(format!("{method} {route}"));
+    }
+    Cow::Borrowed(span.name.as_ref())
+}
+
+/// Default Datadog span type, following the conventions of the official tracers.
+pub fn default_span_type(span: &SpanData) -> Option<&'static str> {
+    if let Some(db_system) = attribute(span, "db.system") {
+        return Some(match db_system {
+            "redis" => "redis",
+            "memcached" => "memcached",
+            "mongodb" => "mongodb",
+            "cassandra" => "cassandra",
+            "elasticsearch" => "elasticsearch",
+            _ => "sql",
+        });
+    }
+    match (&span.span_kind, http_method(span)) {
+        (SpanKind::Server, Some(_)) => Some("web"),
+        (SpanKind::Client, Some(_)) => Some("http"),
+        _ => None,
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Mapping {
+    name: Arc<SpanMappingFn>,
+    resource: Arc<SpanMappingFn>,
+}
+
+impl Default for Mapping {
+    fn default() -> Self {
+        Mapping {
+            name: Arc::new(default_name_mapping),
+            resource: Arc::new(default_resource_mapping),
+        }
+    }
+}
+
+impl Debug for Mapping {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mapping").finish_non_exhaustive()
+    }
+}
+
+impl Mapping {
+    pub(crate) fn with_name(mut self, name: Arc<SpanMappingFn>) -> Self {
+        self.name = name;
+        self
+    }
+
+    pub(crate) fn with_resource(mut self, resource: Arc<SpanMappingFn>) -> Self {
+        self.resource = resource;
+        self
+    }
+
+    pub(crate) fn name<'a>(&self, span: &'a SpanData) -> Cow<'a, str> {
+        attribute(span, OPERATION_NAME_KEY)
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| (self.name)(span))
+    }
+
+    pub(crate) fn resource<'a>(&self, span: &'a SpanData) -> Cow<'a, str> {
+        attribute(span, RESOURCE_NAME_KEY)
+            .map(Cow::Borrowed)
+            .unwrap_or_else(|| (self.resource)(span))
+    }
+
+    pub(crate) fn span_type<'a>(&self, span: &'a SpanData) -> Option<&'a str> {
+        attribute(span, SPAN_TYPE_KEY).or_else(|| default_span_type(span))
+    }
+
+    // records the mapped values as span attributes, so they can be read by the
+    // `opentelemetry_datadog` field mappings
+    fn apply(&self, mut span: SpanData) -> SpanData {
+        let mut attributes = Vec::with_capacity(3);
+        if attribute(&span, OPERATION_NAME_KEY).is_none() {
+            attributes.push(KeyValue::new(
+                OPERATION_NAME_KEY,
+                self.name(&span).into_owned(),
+            ));
+        }
+        if attribute(&span, RESOURCE_NAME_KEY).is_none() {
+            attributes.push(KeyValue::new(
+                RESOURCE_NAME_KEY,
+                self.resource(&span).into_owned(),
+            ));
+        }
+        if attribute(&span, SPAN_TYPE_KEY).is_none() {
+            if let Some(span_type) = self.span_type(&span) {
+                attributes.push(KeyValue::new(SPAN_TYPE_KEY, span_type.to_owned()));
+            }
+        }
+        span.attributes.extend(attributes);
+        span
+    }
+}
+
+/// Exporter applying the [`Mapping`] to spans before handing them to the inner exporter.
+#[derive(Debug)]
+pub(crate) struct MappingExporter<E> {
+    inner: E,
+    mapping: Mapping,
+}
+
+impl<E> MappingExporter<E> {
+    pub(crate) fn new(inner: E, mapping: Mapping) -> Self {
+        MappingExporter { inner, mapping }
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for MappingExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let span_count = batch.len();
+        let batch = batch
+            .into_iter()
+            .map(|span| self.mapping.apply(span))
+            .collect();
+        // the payload size is not known to this wrapper, so no bytes are recorded
+        telemetry::export_attempt();
+        let export = self.inner.export(batch);
+        Box::pin(async move {
+            let result = export.await;
+            if result.is_err() {
+                telemetry::export_error();
+                telemetry::spans_dropped(span_count);
+            }
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<'static, ExportResult> {
+        self.inner.force_flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::span;
+
+    #[test]
+    fn test_http_server_span_mapping() {
+        let span = span(
+            "GET /users/{id}",
+            SpanKind::Server,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new("http.route", "/users/{id}"),
+            ],
+        );
+        let mapping = Mapping::default();
+
+        assert_eq!(mapping.name(&span), "http.request");
+        assert_eq!(mapping.resource(&span), "GET /users/{id}");
+        assert_eq!(mapping.span_type(&span), Some("web"));
+    }
+
+    #[test]
+    fn test_db_span_mapping() {
+        let span = span(
+            "query",
+            SpanKind::Client,
+            vec![
+                KeyValue::new("db.system", "postgresql"),
+                KeyValue::new("db.statement", "SELECT 1"),
+            ],
+        );
+        let mapping = Mapping::default();
+
+        assert_eq!(mapping.name(&span), "postgresql.query");
+        assert_eq!(mapping.resource(&span), "SELECT 1");
+        assert_eq!(mapping.span_type(&span), Some("sql"));
+    }
+
+    #[test]
+    fn test_internal_span_mapping() {
+        let span = span("do_something", SpanKind::Internal, vec![]);
+        let mapping = Mapping::default();
+
+        assert_eq!(mapping.name(&span), "opentelemetry-datadog");
+        assert_eq!(mapping.resource(&span), "do_something");
+        assert_eq!(mapping.span_type(&span), None);
+    }
+
+    #[test]
+    fn test_explicit_fields_take_precedence() {
+        let span = span(
+            "GET",
+            SpanKind::Client,
+            vec![
+                KeyValue::new("http.request.method", "GET"),
+                KeyValue::new(OPERATION_NAME_KEY, "payments.charge"),
+                KeyValue::new(RESOURCE_NAME_KEY, "charge card"),
+                KeyValue::new(SPAN_TYPE_KEY, "custom"),
+            ],
+        );
+        let mapping = Mapping::default().with_name(Arc::new(|_| Cow::Borrowed("ignored")));
+
+        assert_eq!(mapping.name(&span), "payments.charge");
+        assert_eq!(mapping.resource(&span), "charge card");
+        assert_eq!(mapping.span_type(&span), Some("custom"));
+    }
+
+    #[test]
+    fn test_custom_mapping() {
+        let span = span("do_something", SpanKind::Internal, vec![]);
+        let mapping = Mapping::default()
+            .with_name(Arc::new(|_| Cow::Borrowed("internal")))
+            .with_resource(Arc::new(|span| Cow::Owned(span.name.to_uppercase())));
+
+        assert_eq!(mapping.name(&span), "internal");
+        assert_eq!(mapping.resource(&span), "DO_SOMETHING");
+    }
+}
@@ -0,0 +1,225 @@
+//! Bridge between the [`metrics`] crate facade and the DogStatsD client.
+//!
+//! Counters and gauges are sent as DogStatsD counts and gauges, and histograms as
+//! distributions, so percentiles are computed across all the instances of the service.
+//! Labels are sent as `key:value` tags.
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use ::metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SetRecorderError, SharedString, Unit,
+};
+
+use super::{DogStatsD, MetricType};
+
+/// [`Recorder`] sending the metrics of the [`metrics`] crate macros to DogStatsD.
+///
+/// ```no_run
+/// use datadog_tracing::metrics::{DogStatsDBuilder, DogStatsDRecorder};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let statsd = DogStatsDBuilder::from_env()?.build()?;
+/// DogStatsDRecorder::new(statsd).install()?;
+///
+/// metrics::counter!("orders.created", "payment" => "card").increment(1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DogStatsDRecorder {
+    client: DogStatsD,
+    // the macros register their key on every call, so the state of gauges and counters is kept
+    // by handles shared across registrations
+    handles: Arc<Mutex<Handles>>,
+}
+
+#[derive(Default)]
+struct Handles {
+    counters: HashMap<Key, Arc<Handle>>,
+    gauges: HashMap<Key, Arc<Handle>>,
+    histograms: HashMap<Key, Arc<Handle>>,
+}
+
+impl Debug for DogStatsDRecorder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DogStatsDRecorder")
+            .field("client", &self.client)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DogStatsDRecorder {
+    pub fn new(client: DogStatsD) -> Self {
+        DogStatsDRecorder {
+            client,
+            handles: Arc::default(),
+        }
+    }
+
+    /// Installs the recorder as the global [`metrics`] recorder.
+    pub fn install(self) -> Result<(), SetRecorderError<Self>> {
+        ::metrics::set_global_recorder(self)
+    }
+
+    fn handle(
+        &self,
+        key: &Key,
+        handles: impl FnOnce(&mut Handles) -> &mut HashMap<Key, Arc<Handle>>,
+    ) -> Arc<Handle> {
+        let mut guard = self.handles.lock().unwrap();
+        let handles = handles(&mut guard);
+        if let Some(handle) = handles.get(key) {
+            return handle.clone();
+        }
+        let handle = Arc::new(Handle {
+            client: self.client.clone(),
+            name: key.name().to_string(),
+            tags: key
+                .labels()
+                .map(|label| format!("{}:{}", label.key(), label.value()))
+                .collect(),
+            value: AtomicU64::new(0),
+        });
+        handles.insert(key.clone(), handle.clone());
+        handle
+    }
+}
+
+impl Recorder for DogStatsDRecorder {
+    // descriptions are not supported by DogStatsD
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(self.handle(key, |handles| &mut handles.counters))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(self.handle(key, |handles| &mut handles.gauges))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(self.handle(key, |handles| &mut handles.histograms))
+    }
+}
+
+struct Handle {
+    client: DogStatsD,
+    name: String,
+    tags: Vec<String>,
+    // last absolute value of counters, or the bits of the current value of gauges
+    value: AtomicU64,
+}
+
+impl Handle {
+    fn send<V: std::fmt::Display>(&self, value: V, metric_type: MetricType) {
+        self.client.send(&self.name, value, metric_type, &self.tags)
+    }
+
+    // DogStatsD gauges are absolute, so increments are applied to the last known value
+    fn update_gauge(&self, f: impl Fn(f64) -> f64) {
+        let previous = self
+            .value
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |bits| {
+                Some(f(f64::from_bits(bits)).to_bits())
+            })
+            .unwrap_or_default();
+        self.send(f(f64::from_bits(previous)), MetricType::Gauge);
+    }
+}
+
+impl CounterFn for Handle {
+    fn increment(&self, value: u64) {
+        self.send(value, MetricType::Count);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.value.swap(value, Ordering::AcqRel);
+        if value > previous {
+            self.send(value - previous, MetricType::Count);
+        }
+    }
+}
+
+impl GaugeFn for Handle {
+    fn increment(&self, value: f64) {
+        self.update_gauge(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update_gauge(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.value.store(value.to_bits(), Ordering::Release);
+        self.send(value, MetricType::Gauge);
+    }
+}
+
+impl HistogramFn for Handle {
+    fn record(&self, value: f64) {
+        self.send(value, MetricType::Distribution);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::tests::{receive, udp_server};
+    use crate::metrics::DogStatsDBuilder;
+
+    #[test]
+    fn test_metrics_facade() {
+        let (server, url) = udp_server();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(url)
+            .build()
+            .unwrap();
+        let recorder = DogStatsDRecorder::new(statsd);
+
+        ::metrics::with_local_recorder(&recorder, || {
+            ::metrics::counter!("requests", "route" => "/users").increment(2);
+            let gauge = ::metrics::gauge!("connections");
+            gauge.increment(3.0);
+            gauge.decrement(1.0);
+            ::metrics::histogram!("latency").record(0.5);
+        });
+
+        assert_eq!(
+            receive(&server),
+            "requests:2|c|#route:/users,service:my-service"
+        );
+        assert_eq!(receive(&server), "connections:3|g|#service:my-service");
+        assert_eq!(receive(&server), "connections:2|g|#service:my-service");
+        assert_eq!(receive(&server), "latency:0.5|d|#service:my-service");
+    }
+
+    #[test]
+    fn test_metrics_facade_state_across_calls() {
+        let (server, url) = udp_server();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(url)
+            .build()
+            .unwrap();
+        let recorder = DogStatsDRecorder::new(statsd);
+
+        // every macro call registers the key again
+        ::metrics::with_local_recorder(&recorder, || {
+            ::metrics::gauge!("x").increment(1.0);
+            ::metrics::gauge!("x").increment(1.0);
+            ::metrics::counter!("total").absolute(5);
+            ::metrics::counter!("total").absolute(7);
+        });
+
+        assert_eq!(receive(&server), "x:1|g|#service:my-service");
+        assert_eq!(receive(&server), "x:2|g|#service:my-service");
+        assert_eq!(receive(&server), "total:5|c|#service:my-service");
+        assert_eq!(receive(&server), "total:2|c|#service:my-service");
+    }
+}
@@ -0,0 +1,400 @@
+//! DogStatsD metrics client.
+//!
+//! This module contains a client sending custom metrics to the Datadog agent over the
+//! DogStatsD protocol, using UDP (`udp://localhost:8125` by default) or a Unix domain socket
+//! (`unix:///var/run/datadog/dsd.socket`).
+//!
+//! The client is configured like the tracer: the `service`, `env` and `version` unified service
+//! tags are added to every metric. Metrics are sent on a best effort basis: sending never
+//! blocks and errors are ignored, so instrumentation can't impact the application.
+//!
+//! ```no_run
+//! use datadog_tracing::metrics::DogStatsDBuilder;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let statsd = DogStatsDBuilder::new("my-service").with_env("prod").build()?;
+//! statsd.incr("orders.created", &["payment:card"]);
+//! statsd.distribution("orders.amount", 42.5, &[]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! With the `metrics-facade` feature, [`DogStatsDRecorder`] ships the metrics recorded with the
+//! [`metrics`](https://docs.rs/metrics) crate macros.
+use std::env;
+use std::fmt::Write;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+#[cfg(feature = "metrics-facade")]
+mod facade;
+
+#[cfg(feature = "metrics-facade")]
+pub use facade::DogStatsDRecorder;
+
+const UNIX_SCHEME: &str = "unix://";
+const UDP_SCHEME: &str = "udp://";
+
+static GLOBAL_CLIENT: OnceLock<DogStatsD> = OnceLock::new();
+
+/// Returns the client installed by [`set_global`], if any.
+pub fn global() -> Option<&'static DogStatsD> {
+    GLOBAL_CLIENT.get()
+}
+
+/// Installs the client returned by [`global`]. Returns the client back if one was already set.
+pub fn set_global(client: DogStatsD) -> Result<(), DogStatsD> {
+    GLOBAL_CLIENT.set(client)
+}
+
+/// Type of a DogStatsD metric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetricType {
+    Count,
+    Gauge,
+    Histogram,
+    Distribution,
+}
+
+impl MetricType {
+    fn as_str(self) -> &'static str {
+        match self {
+            MetricType::Count => "c",
+            MetricType::Gauge => "g",
+            MetricType::Histogram => "h",
+            MetricType::Distribution => "d",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Udp(UdpSocket),
+    #[cfg(unix)]
+    Uds(UnixDatagram, PathBuf),
+}
+
+impl Transport {
+    fn send(&self, datagram: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Udp(socket) => socket.send(datagram),
+            #[cfg(unix)]
+            Transport::Uds(socket, path) => socket.send_to(datagram, path),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    transport: Transport,
+    prefix: Option<String>,
+    // constant tags, pre-formatted as `tag1,tag2`
+    tags: String,
+}
+
+/// DogStatsD client.
+///
+/// It is cheap to clone, clones share the same socket.
+#[derive(Debug, Clone)]
+pub struct DogStatsD {
+    inner: Arc<Inner>,
+}
+
+impl DogStatsD {
+    /// Increments a counter by 1.
+    pub fn incr(&self, name: &str, tags: &[&str]) {
+        self.count(name, 1, tags)
+    }
+
+    /// Adds `value` to a counter.
+    pub fn count(&self, name: &str, value: i64, tags: &[&str]) {
+        self.send(name, value, MetricType::Count, tags)
+    }
+
+    /// Sets the value of a gauge.
+    pub fn gauge(&self, name: &str, value: f64, tags: &[&str]) {
+        self.send(name, value, MetricType::Gauge, tags)
+    }
+
+    /// Records a value in a histogram, aggregated by the agent.
+    pub fn histogram(&self, name: &str, value: f64, tags: &[&str]) {
+        self.send(name, value, MetricType::Histogram, tags)
+    }
+
+    /// Records a value in a distribution, aggregated globally by Datadog.
+    pub fn distribution(&self, name: &str, value: f64, tags: &[&str]) {
+        self.send(name, value, MetricType::Distribution, tags)
+    }
+
+    pub(crate) fn send<V: std::fmt::Display, T: AsRef<str>>(
+        &self,
+        name: &str,
+        value: V,
+        metric_type: MetricType,
+        tags: &[T],
+    ) {
+        let datagram = self.format(name, value, metric_type, tags);
+        // metrics are best effort, a full buffer or a missing agent must not impact the caller
+        let _ = self.inner.transport.send(datagram.as_bytes());
+    }
+
+    fn format<V: std::fmt::Display, T: AsRef<str>>(
+        &self,
+        name: &str,
+        value: V,
+        metric_type: MetricType,
+        tags: &[T],
+    ) -> String {
+        let mut datagram = String::with_capacity(64);
+        if let Some(prefix) = &self.inner.prefix {
+            datagram.push_str(prefix);
+            datagram.push('.');
+        }
+        let _ = write!(datagram, "{name}:{value}|{}", metric_type.as_str());
+
+        // the constant tags always contain at least the service
+        datagram.push_str("|#");
+        for tag in tags {
+            datagram.push_str(tag.as_ref());
+            datagram.push(',');
+        }
+        datagram.push_str(&self.inner.tags);
+        datagram
+    }
+}
+
+/// Builder for a [`DogStatsD`] client.
+#[derive(Debug, Clone)]
+pub struct DogStatsDBuilder {
+    service_name: String,
+    env: Option<String>,
+    version: Option<String>,
+    url: String,
+    prefix: Option<String>,
+    tags: Vec<String>,
+}
+
+impl DogStatsDBuilder {
+    pub fn new<T: Into<String>>(service_name: T) -> Self {
+        DogStatsDBuilder {
+            service_name: service_name.into(),
+            env: None,
+            version: None,
+            url: format!("{UDP_SCHEME}localhost:8125"),
+            prefix: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Creates a builder configured from the `DD_SERVICE`, `DD_ENV`, `DD_VERSION`,
+    /// `DD_AGENT_HOST`, `DD_DOGSTATSD_PORT` and `DD_DOGSTATSD_URL` environment variables.
+    pub fn from_env() -> io::Result<Self> {
+        let service_name = env::var("DD_SERVICE")
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "missing DD_SERVICE"))?;
+
+        let mut builder = Self::new(service_name);
+        builder.env = env::var("DD_ENV").ok();
+        builder.version = env::var("DD_VERSION").ok();
+        builder.url = env::var("DD_DOGSTATSD_URL").unwrap_or_else(|_| {
+            let dd_host = env::var("DD_AGENT_HOST").unwrap_or("localhost".to_string());
+            let dd_port = env::var("DD_DOGSTATSD_PORT")
+                .ok()
+                .and_then(|it| it.parse::<u16>().ok())
+                .unwrap_or(8125);
+            format!("{UDP_SCHEME}{dd_host}:{dd_port}")
+        });
+
+        Ok(builder)
+    }
+
+    /// Sets the `env` tag added to every metric.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Sets the `version` tag added to every metric.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Sets the DogStatsD endpoint, `udp://localhost:8125` by default.
+    ///
+    /// Unix domain sockets are supported with the `unix://` scheme,
+    /// e.g. `unix:///var/run/datadog/dsd.socket`.
+    pub fn with_url<T: Into<String>>(mut self, url: T) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Sets a prefix added to every metric name, separated by a `.`.
+    pub fn with_prefix<T: Into<String>>(mut self, prefix: T) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Adds a constant `key:value` tag to every metric.
+    pub fn with_tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Builds the client, opening its socket.
+    pub fn build(self) -> io::Result<DogStatsD> {
+        let transport = self.transport()?;
+
+        let tags = [
+            Some(format!("service:{}", self.service_name)),
+            self.env.map(|env| format!("env:{env}")),
+            self.version.map(|version| format!("version:{version}")),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.tags)
+        .collect::<Vec<_>>()
+        .join(",");
+
+        Ok(DogStatsD {
+            inner: Arc::new(Inner {
+                transport,
+                prefix: self.prefix,
+                tags,
+            }),
+        })
+    }
+
+    fn transport(&self) -> io::Result<Transport> {
+        #[cfg(unix)]
+        if let Some(path) = self.url.strip_prefix(UNIX_SCHEME) {
+            let socket = UnixDatagram::unbound()?;
+            socket.set_nonblocking(true)?;
+            return Ok(Transport::Uds(socket, PathBuf::from(path)));
+        }
+
+        let address = self.url.strip_prefix(UDP_SCHEME).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported DogStatsD url {}", self.url),
+            )
+        })?;
+        let target = address.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("no address for DogStatsD url {}", self.url),
+            )
+        })?;
+        // bound to the address family of the target, e.g. to reach an IPv6 agent
+        let local: SocketAddr = match target {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        socket.connect(target)?;
+        Ok(Transport::Udp(socket))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    pub(crate) fn udp_server() -> (UdpSocket, String) {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let url = format!("udp://{}", server.local_addr().unwrap());
+        (server, url)
+    }
+
+    pub(crate) fn receive(server: &UdpSocket) -> String {
+        let mut buffer = [0; 1024];
+        let size = server.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..size]).into_owned()
+    }
+
+    #[test]
+    fn test_udp_metrics() {
+        let (server, url) = udp_server();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_env("test")
+            .with_version("1.0")
+            .with_url(url)
+            .build()
+            .unwrap();
+
+        statsd.incr("requests", &["route:/users"]);
+        statsd.gauge("queue.size", 12.5, &[]);
+        statsd.histogram("latency", 3.0, &["a:b", "c:d"]);
+        statsd.distribution("amount", 42.0, &[]);
+
+        let tags = "service:my-service,env:test,version:1.0";
+        assert_eq!(
+            receive(&server),
+            format!("requests:1|c|#route:/users,{tags}")
+        );
+        assert_eq!(receive(&server), format!("queue.size:12.5|g|#{tags}"));
+        assert_eq!(receive(&server), format!("latency:3|h|#a:b,c:d,{tags}"));
+        assert_eq!(receive(&server), format!("amount:42|d|#{tags}"));
+    }
+
+    #[test]
+    fn test_udp_address_family() {
+        let server = UdpSocket::bind("[::1]:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let url = format!("udp://{}", server.local_addr().unwrap());
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(url)
+            .build()
+            .unwrap();
+
+        statsd.incr("requests", &[]);
+        assert_eq!(receive(&server), "requests:1|c|#service:my-service");
+
+        let result = DogStatsDBuilder::new("my-service")
+            .with_url("udp://unresolvable.invalid:8125")
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_uds_metrics() {
+        let directory = std::env::temp_dir().join(format!("dsd-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("dsd.socket");
+        let _ = std::fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(format!("unix://{}", path.display()))
+            .with_prefix("app")
+            .build()
+            .unwrap();
+
+        statsd.count("jobs", 3, &[]);
+
+        let mut buffer = [0; 1024];
+        let size = server.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"app.jobs:3|c|#service:my-service");
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_url() {
+        let result = DogStatsDBuilder::new("my-service")
+            .with_url("http://localhost:8125")
+            .build();
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,100 @@
+//! OTLP export to the Datadog agent's OTLP intake.
+//!
+//! Enabled with the `otlp` feature, this module builds an [`opentelemetry_otlp`] exporter,
+//! over gRPC or HTTP/protobuf, for agents running with OTLP ingestion enabled.
+//!
+//! The unified service tags are sent as the resource attributes the agent maps to them:
+//! `service.name`, `deployment.environment` and `service.version`.
+use std::str::FromStr;
+
+use opentelemetry::trace::{TraceError, TraceResult};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+
+/// Protocol used to send spans to the OTLP intake.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OtlpProtocol {
+    /// OTLP over gRPC, on port 4317 by default
+    #[default]
+    Grpc,
+    /// OTLP over HTTP with protobuf payloads, on port 4318 by default
+    HttpProtobuf,
+}
+
+impl OtlpProtocol {
+    pub fn default_port(self) -> u16 {
+        match self {
+            OtlpProtocol::Grpc => 4317,
+            OtlpProtocol::HttpProtobuf => 4318,
+        }
+    }
+}
+
+impl FromStr for OtlpProtocol {
+    type Err = TraceError;
+
+    /// Parses the values of `OTEL_EXPORTER_OTLP_PROTOCOL`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grpc" => Ok(OtlpProtocol::Grpc),
+            "http/protobuf" => Ok(OtlpProtocol::HttpProtobuf),
+            _ => Err(format!("unsupported otlp protocol {s}").into()),
+        }
+    }
+}
+
+pub(crate) fn build_exporter(protocol: OtlpProtocol, endpoint: &str) -> TraceResult<SpanExporter> {
+    match protocol {
+        OtlpProtocol::Grpc => opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(endpoint)
+            .build_span_exporter(),
+        OtlpProtocol::HttpProtobuf => opentelemetry_otlp::new_exporter()
+            .http()
+            .with_endpoint(endpoint)
+            .build_span_exporter(),
+    }
+}
+
+pub(crate) fn resource(service: &str, env: Option<&str>, version: Option<&str>) -> Resource {
+    let mut attributes = vec![KeyValue::new("service.name", service.to_string())];
+    if let Some(env) = env {
+        attributes.push(KeyValue::new("deployment.environment", env.to_string()));
+    }
+    if let Some(version) = version {
+        attributes.push(KeyValue::new("service.version", version.to_string()));
+    }
+    Resource::new(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::Key;
+
+    #[test]
+    fn test_protocol_from_str() {
+        assert_eq!("grpc".parse::<OtlpProtocol>().unwrap(), OtlpProtocol::Grpc);
+        assert_eq!(
+            "http/protobuf".parse::<OtlpProtocol>().unwrap(),
+            OtlpProtocol::HttpProtobuf
+        );
+        assert!("http/json".parse::<OtlpProtocol>().is_err());
+    }
+
+    #[test]
+    fn test_resource_contains_unified_service_tags() {
+        let resource = resource("my-service", Some("prod"), None);
+
+        assert_eq!(
+            resource.get(Key::new("service.name")),
+            Some("my-service".into())
+        );
+        assert_eq!(
+            resource.get(Key::new("deployment.environment")),
+            Some("prod".into())
+        );
+        assert_eq!(resource.get(Key::new("service.version")), None);
+    }
+}
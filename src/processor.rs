@@ -0,0 +1,153 @@
+//! Span processors used by the Datadog tracer.
+//!
+//! [`DatadogSpanProcessor`] adds the Datadog specific tags that depend on the trace context:
+//!
+//! - `_dd.origin`: the trace origin (set by Synthetics or RUM through the `x-datadog-origin`
+//!   header), copied from the span context into every span, so Datadog can bill and display
+//!   these traces correctly.
+//! - `_top_level`: set on local root spans, i.e. spans without a parent or with a remote parent.
+//! - `runtime-id`: the identifier of the process (see [`runtime_id`]), set on local root spans
+//!   to correlate traces with runtime metrics.
+use std::sync::OnceLock;
+
+use opentelemetry::trace::{Span as _, TraceContextExt, TraceResult};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::{Span, SpanProcessor};
+
+use crate::propagator::{origin_from_trace_state, DATADOG_ORIGIN_TAG};
+use crate::telemetry;
+
+/// Span tag flagging the local root span of a trace.
+pub const TOP_LEVEL_TAG: &str = "_top_level";
+/// Span tag holding the [`runtime_id`].
+pub const RUNTIME_ID_TAG: &str = "runtime-id";
+
+static RUNTIME_ID: OnceLock<String> = OnceLock::new();
+
+/// Returns the identifier of this process, a random UUID generated on first use.
+pub fn runtime_id() -> &'static str {
+    RUNTIME_ID.get_or_init(|| uuid::Uuid::new_v4().to_string())
+}
+
+#[derive(Debug, Default)]
+pub struct DatadogSpanProcessor;
+
+impl SpanProcessor for DatadogSpanProcessor {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        telemetry::span_started();
+
+        let origin = origin_from_trace_state(span.span_context().trace_state()).map(str::to_owned);
+        if let Some(origin) = origin {
+            span.set_attribute(KeyValue::new(DATADOG_ORIGIN_TAG, origin));
+        }
+
+        if !cx.has_active_span() || cx.span().span_context().is_remote() {
+            span.set_attribute(KeyValue::new(TOP_LEVEL_TAG, 1));
+            span.set_attribute(KeyValue::new(RUNTIME_ID_TAG, runtime_id()));
+        }
+    }
+
+    fn on_end(&self, span: SpanData) {
+        telemetry::span_finished(span.span_context.is_sampled());
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{runtime_id, DatadogSpanProcessor, RUNTIME_ID_TAG, TOP_LEVEL_TAG};
+    use crate::propagator::DatadogPropagator;
+    use opentelemetry::propagation::TextMapPropagator;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::Value;
+    use opentelemetry_sdk::export::trace::SpanData;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use opentelemetry_sdk::trace::TracerProvider;
+    use std::collections::HashMap;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    fn record_spans(headers: &[(&str, &str)]) -> Vec<SpanData> {
+        let exporter = InMemorySpanExporter::default();
+        let provider = TracerProvider::builder()
+            .with_span_processor(DatadogSpanProcessor)
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let parent_cx = DatadogPropagator::default().extract(&headers);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            root.set_parent(parent_cx);
+            root.in_scope(|| {
+                let _child = tracing::info_span!("child").entered();
+            });
+        });
+
+        provider.force_flush();
+        exporter.get_finished_spans().unwrap()
+    }
+
+    #[test]
+    fn test_origin_is_set_on_all_spans_of_the_trace() {
+        let spans = record_spans(&[
+            ("x-datadog-trace-id", "1234"),
+            ("x-datadog-parent-id", "12"),
+            ("x-datadog-sampling-priority", "1"),
+            ("x-datadog-origin", "synthetics"),
+        ]);
+
+        assert_eq!(spans.len(), 2);
+        for span in spans {
+            assert_eq!(
+                attribute(&span, "_dd.origin"),
+                Some(Value::from("synthetics")),
+                "{}",
+                span.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_top_level_is_set_on_local_root_spans() {
+        let spans = record_spans(&[
+            ("x-datadog-trace-id", "1234"),
+            ("x-datadog-parent-id", "12"),
+            ("x-datadog-sampling-priority", "1"),
+        ]);
+
+        let top_level = |name: &str| {
+            let span = spans.iter().find(|span| span.name == name).unwrap();
+            attribute(span, TOP_LEVEL_TAG)
+        };
+        assert_eq!(top_level("root"), Some(Value::I64(1)));
+        assert_eq!(top_level("child"), None);
+        let root = spans.iter().find(|span| span.name == "root").unwrap();
+        assert_eq!(
+            attribute(root, RUNTIME_ID_TAG),
+            Some(Value::from(runtime_id()))
+        );
+        assert_eq!(attribute(&spans[0], "_dd.origin"), None);
+    }
+}
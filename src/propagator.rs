@@ -0,0 +1,325 @@
+//! Datadog propagation.
+//!
+//! This module contains a [`TextMapPropagator`] that extracts and injects span
+//! contexts using Datadog's `x-datadog-*` headers. It is based on the propagator
+//! provided by [`opentelemetry_datadog`], but it also handles the `x-datadog-origin`
+//! header used by Synthetics and RUM.
+//!
+//! The origin is kept in the span context [`TraceState`] under the `dd` key (`o:<origin>`),
+//! the same layout used by Datadog for the W3C `tracestate` header. Since the trace state is
+//! inherited by child spans, every span of the trace has access to the origin.
+//!
+//! [`opentelemetry_datadog`]: https://github.com/open-telemetry/opentelemetry-rust-contrib/tree/main/opentelemetry-datadog
+use std::sync::OnceLock;
+
+use opentelemetry::propagation::text_map_propagator::FieldIter;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState};
+use opentelemetry::Context;
+
+const DATADOG_TRACE_ID_HEADER: &str = "x-datadog-trace-id";
+const DATADOG_PARENT_ID_HEADER: &str = "x-datadog-parent-id";
+const DATADOG_SAMPLING_PRIORITY_HEADER: &str = "x-datadog-sampling-priority";
+const DATADOG_ORIGIN_HEADER: &str = "x-datadog-origin";
+
+/// Trace state key holding Datadog specific values.
+const DATADOG_TRACE_STATE_KEY: &str = "dd";
+const ORIGIN_TRACE_STATE_PREFIX: &str = "o:";
+
+/// Span tag Datadog uses to identify the origin of a trace (e.g. `synthetics`, `rum`).
+pub const DATADOG_ORIGIN_TAG: &str = "_dd.origin";
+
+const TRACE_FLAG_DEFERRED: TraceFlags = TraceFlags::new(0x02);
+
+static DATADOG_HEADER_FIELDS: OnceLock<[String; 4]> = OnceLock::new();
+
+enum SamplingPriority {
+    UserReject = -1,
+    AutoReject = 0,
+    AutoKeep = 1,
+    UserKeep = 2,
+}
+
+#[derive(Debug)]
+enum ExtractError {
+    TraceId,
+    SpanId,
+    SamplingPriority,
+}
+
+/// Returns the Datadog origin stored in a trace state, if any.
+pub fn origin_from_trace_state(trace_state: &TraceState) -> Option<&str> {
+    trace_state
+        .get(DATADOG_TRACE_STATE_KEY)?
+        .split(';')
+        .find_map(|entry| entry.strip_prefix(ORIGIN_TRACE_STATE_PREFIX))
+        .filter(|origin| !origin.is_empty())
+}
+
+// the trace state value can't contain `,` or `=`, and `;` is the Datadog entries separator
+fn sanitize_origin(origin: &str) -> String {
+    origin
+        .chars()
+        .map(|c| match c {
+            ',' | ';' | '=' | '~' => '_',
+            c if (' '..='~').contains(&c) => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Extracts and injects `SpanContext`s into `Extractor`s or `Injector`s using Datadog's header format.
+///
+/// ## Example
+///
+/// ```
+/// use opentelemetry::global;
+/// use datadog_tracing::propagator::DatadogPropagator;
+///
+/// global::set_text_map_propagator(DatadogPropagator::default());
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DatadogPropagator {
+    _private: (),
+}
+
+impl DatadogPropagator {
+    /// Creates a new `DatadogPropagator`.
+    pub fn new() -> Self {
+        DatadogPropagator::default()
+    }
+
+    fn extract_trace_id(&self, trace_id: &str) -> Result<TraceId, ExtractError> {
+        trace_id
+            .parse::<u64>()
+            .map(|id| TraceId::from(id as u128))
+            .map_err(|_| ExtractError::TraceId)
+    }
+
+    fn extract_span_id(&self, span_id: &str) -> Result<SpanId, ExtractError> {
+        span_id
+            .parse::<u64>()
+            .map(SpanId::from)
+            .map_err(|_| ExtractError::SpanId)
+    }
+
+    fn extract_sampling_priority(
+        &self,
+        sampling_priority: &str,
+    ) -> Result<SamplingPriority, ExtractError> {
+        let i = sampling_priority
+            .parse::<i32>()
+            .map_err(|_| ExtractError::SamplingPriority)?;
+
+        match i {
+            -1 => Ok(SamplingPriority::UserReject),
+            0 => Ok(SamplingPriority::AutoReject),
+            1 => Ok(SamplingPriority::AutoKeep),
+            2 => Ok(SamplingPriority::UserKeep),
+            _ => Err(ExtractError::SamplingPriority),
+        }
+    }
+
+    fn extract_trace_state(&self, extractor: &dyn Extractor) -> TraceState {
+        extractor
+            .get(DATADOG_ORIGIN_HEADER)
+            .map(str::trim)
+            .filter(|origin| !origin.is_empty())
+            .and_then(|origin| {
+                TraceState::from_key_value([(
+                    DATADOG_TRACE_STATE_KEY,
+                    format!("{ORIGIN_TRACE_STATE_PREFIX}{}", sanitize_origin(origin)),
+                )])
+                .ok()
+            })
+            .unwrap_or_default()
+    }
+
+    fn extract_span_context(&self, extractor: &dyn Extractor) -> Result<SpanContext, ExtractError> {
+        let trace_id =
+            self.extract_trace_id(extractor.get(DATADOG_TRACE_ID_HEADER).unwrap_or(""))?;
+        // If we have a trace_id but can't get the parent span, we default it to invalid instead of completely erroring
+        // out so that the rest of the spans aren't completely lost
+        let span_id = self
+            .extract_span_id(extractor.get(DATADOG_PARENT_ID_HEADER).unwrap_or(""))
+            .unwrap_or(SpanId::INVALID);
+        let sampling_priority = self.extract_sampling_priority(
+            extractor
+                .get(DATADOG_SAMPLING_PRIORITY_HEADER)
+                .unwrap_or(""),
+        );
+        let sampled = match sampling_priority {
+            Ok(SamplingPriority::UserReject) | Ok(SamplingPriority::AutoReject) => {
+                TraceFlags::default()
+            }
+            Ok(SamplingPriority::UserKeep) | Ok(SamplingPriority::AutoKeep) => TraceFlags::SAMPLED,
+            // Treat the sampling as DEFERRED instead of erroring on extracting the span context
+            Err(_) => TRACE_FLAG_DEFERRED,
+        };
+
+        Ok(SpanContext::new(
+            trace_id,
+            span_id,
+            sampled,
+            true,
+            self.extract_trace_state(extractor),
+        ))
+    }
+}
+
+impl TextMapPropagator for DatadogPropagator {
+    fn inject_context(&self, cx: &Context, injector: &mut dyn Injector) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if span_context.is_valid() {
+            injector.set(
+                DATADOG_TRACE_ID_HEADER,
+                (u128::from_be_bytes(span_context.trace_id().to_bytes()) as u64).to_string(),
+            );
+            injector.set(
+                DATADOG_PARENT_ID_HEADER,
+                u64::from_be_bytes(span_context.span_id().to_bytes()).to_string(),
+            );
+
+            if span_context.trace_flags() & TRACE_FLAG_DEFERRED != TRACE_FLAG_DEFERRED {
+                let sampling_priority = if span_context.is_sampled() {
+                    SamplingPriority::AutoKeep
+                } else {
+                    SamplingPriority::AutoReject
+                };
+
+                injector.set(
+                    DATADOG_SAMPLING_PRIORITY_HEADER,
+                    (sampling_priority as i32).to_string(),
+                );
+            }
+
+            if let Some(origin) = origin_from_trace_state(span_context.trace_state()) {
+                injector.set(DATADOG_ORIGIN_HEADER, origin.to_string());
+            }
+        }
+    }
+
+    fn extract_with_context(&self, cx: &Context, extractor: &dyn Extractor) -> Context {
+        self.extract_span_context(extractor)
+            .map(|sc| cx.with_remote_span_context(sc))
+            .unwrap_or_else(|_| cx.clone())
+    }
+
+    fn fields(&self) -> FieldIter<'_> {
+        FieldIter::new(DATADOG_HEADER_FIELDS.get_or_init(|| {
+            [
+                DATADOG_TRACE_ID_HEADER.to_string(),
+                DATADOG_PARENT_ID_HEADER.to_string(),
+                DATADOG_SAMPLING_PRIORITY_HEADER.to_string(),
+                DATADOG_ORIGIN_HEADER.to_string(),
+            ]
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn extract(headers: &[(&str, &str)]) -> Context {
+        let map: HashMap<String, String> = headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        DatadogPropagator::default().extract(&map)
+    }
+
+    #[test]
+    fn test_extract_span_context() {
+        let cx = extract(&[
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_SAMPLING_PRIORITY_HEADER, "1"),
+        ]);
+        let span = cx.span();
+        let span_context = span.span_context();
+
+        assert_eq!(span_context.trace_id(), TraceId::from(1234u128));
+        assert_eq!(span_context.span_id(), SpanId::from(12u64));
+        assert!(span_context.is_sampled());
+        assert!(span_context.is_remote());
+        assert_eq!(origin_from_trace_state(span_context.trace_state()), None);
+    }
+
+    #[test]
+    fn test_extract_invalid_trace_id_returns_empty_context() {
+        let cx = extract(&[(DATADOG_TRACE_ID_HEADER, "garbage")]);
+
+        assert!(!cx.has_active_span());
+    }
+
+    #[test]
+    fn test_extract_origin() {
+        let cx = extract(&[
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_PARENT_ID_HEADER, "12"),
+            (DATADOG_ORIGIN_HEADER, "synthetics-browser"),
+        ]);
+
+        assert_eq!(
+            origin_from_trace_state(cx.span().span_context().trace_state()),
+            Some("synthetics-browser")
+        );
+    }
+
+    #[test]
+    fn test_extract_sanitizes_origin() {
+        let cx = extract(&[
+            (DATADOG_TRACE_ID_HEADER, "1234"),
+            (DATADOG_ORIGIN_HEADER, "rum,a=b;c"),
+        ]);
+
+        assert_eq!(
+            origin_from_trace_state(cx.span().span_context().trace_state()),
+            Some("rum_a_b_c")
+        );
+    }
+
+    #[test]
+    fn test_inject_origin() {
+        let trace_state = TraceState::from_key_value([("dd", "o:rum")]).unwrap();
+        let span_context = SpanContext::new(
+            TraceId::from(1234u128),
+            SpanId::from(12u64),
+            TraceFlags::SAMPLED,
+            true,
+            trace_state,
+        );
+        let mut injector: HashMap<String, String> = HashMap::new();
+        DatadogPropagator::default().inject_context(
+            &Context::new().with_remote_span_context(span_context),
+            &mut injector,
+        );
+
+        assert_eq!(injector.get(DATADOG_TRACE_ID_HEADER).unwrap(), "1234");
+        assert_eq!(injector.get(DATADOG_PARENT_ID_HEADER).unwrap(), "12");
+        assert_eq!(injector.get(DATADOG_SAMPLING_PRIORITY_HEADER).unwrap(), "1");
+        assert_eq!(injector.get(DATADOG_ORIGIN_HEADER).unwrap(), "rum");
+    }
+
+    #[test]
+    fn test_inject_without_origin() {
+        let span_context = SpanContext::new(
+            TraceId::from(1234u128),
+            SpanId::from(12u64),
+            TRACE_FLAG_DEFERRED,
+            true,
+            TraceState::default(),
+        );
+        let mut injector: HashMap<String, String> = HashMap::new();
+        DatadogPropagator::default().inject_context(
+            &Context::new().with_remote_span_context(span_context),
+            &mut injector,
+        );
+
+        assert!(!injector.contains_key(DATADOG_ORIGIN_HEADER));
+        assert!(!injector.contains_key(DATADOG_SAMPLING_PRIORITY_HEADER));
+    }
+}
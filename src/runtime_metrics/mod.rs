@@ -0,0 +1,332 @@
+//! Runtime metrics: Tokio runtime and process metrics.
+//!
+//! [`RuntimeMetricsReporter`] periodically samples the [`RuntimeMetrics`] of a Tokio runtime
+//! and sends them to DogStatsD as `runtime.rust.tokio.*` metrics, tagged with the unified
+//! service tags of the client and the [`runtime_id`] of the process:
+//!
+//! | metric                                   | type  | description                                 |
+//! |------------------------------------------|-------|---------------------------------------------|
+//! | `runtime.rust.tokio.workers`             | gauge | number of worker threads                    |
+//! | `runtime.rust.tokio.alive_tasks`         | gauge | number of alive tasks                       |
+//! | `runtime.rust.tokio.global_queue_depth`  | gauge | tasks waiting in the global queue           |
+//! | `runtime.rust.tokio.busy_ratio`          | gauge | share of the interval workers spent busy    |
+//! | `runtime.rust.tokio.park_count`          | count | times workers parked, i.e. ran out of work  |
+//!
+//! When built with `RUSTFLAGS="--cfg tokio_unstable"`, the unstable Tokio metrics are also sent:
+//! `blocking_threads`, `idle_blocking_threads`, `blocking_queue_depth`, `local_queue_depth`
+//! (gauges), and `poll_count`, `steal_count` and `spawned_tasks` (counts).
+//!
+//! On Linux, the process metrics read from `/proc/self` are also sent:
+//!
+//! | metric                        | type  | description                                  |
+//! |-------------------------------|-------|----------------------------------------------|
+//! | `runtime.rust.mem.rss`        | gauge | resident set size, in bytes                  |
+//! | `runtime.rust.mem.virtual`    | gauge | virtual memory size, in bytes                |
+//! | `runtime.rust.cpu.time.user`  | gauge | user CPU time of the interval, in seconds    |
+//! | `runtime.rust.cpu.time.sys`   | gauge | system CPU time of the interval, in seconds  |
+//! | `runtime.rust.thread.count`   | gauge | number of threads                            |
+//! | `runtime.rust.fd.count`       | gauge | number of open file descriptors              |
+//!
+//! The reporter is started by [`crate::init`] when `DD_RUNTIME_METRICS_ENABLED` is `true`.
+use std::time::{Duration, Instant};
+
+use tokio::runtime::{Handle, RuntimeMetrics};
+use tokio::task::JoinHandle;
+
+#[cfg(target_os = "linux")]
+mod process;
+
+#[cfg(target_os = "linux")]
+use self::process::ProcessStats;
+use crate::metrics::DogStatsD;
+use crate::processor::{runtime_id, RUNTIME_ID_TAG};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Cumulative counters of the previous sample, to send deltas.
+#[derive(Debug, Default)]
+struct Counters {
+    busy_duration: Duration,
+    park_count: u64,
+    #[cfg(tokio_unstable)]
+    poll_count: u64,
+    #[cfg(tokio_unstable)]
+    steal_count: u64,
+    #[cfg(tokio_unstable)]
+    spawned_tasks: u64,
+}
+
+impl Counters {
+    fn read(metrics: &RuntimeMetrics) -> Self {
+        let workers = 0..metrics.num_workers();
+        Counters {
+            busy_duration: workers
+                .clone()
+                .map(|worker| metrics.worker_total_busy_duration(worker))
+                .sum(),
+            park_count: workers
+                .clone()
+                .map(|worker| metrics.worker_park_count(worker))
+                .sum(),
+            #[cfg(tokio_unstable)]
+            poll_count: workers
+                .clone()
+                .map(|worker| metrics.worker_poll_count(worker))
+                .sum(),
+            #[cfg(tokio_unstable)]
+            steal_count: workers
+                .clone()
+                .map(|worker| metrics.worker_steal_count(worker))
+                .sum(),
+            #[cfg(tokio_unstable)]
+            spawned_tasks: metrics.spawned_tasks_count(),
+        }
+    }
+}
+
+/// Reporter sending the metrics of a Tokio runtime to DogStatsD.
+///
+/// ```no_run
+/// use datadog_tracing::metrics::DogStatsDBuilder;
+/// use datadog_tracing::runtime_metrics::RuntimeMetricsReporter;
+///
+/// # #[tokio::main]
+/// # async fn main() -> std::io::Result<()> {
+/// let statsd = DogStatsDBuilder::from_env()?.build()?;
+/// RuntimeMetricsReporter::new(statsd).spawn();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RuntimeMetricsReporter {
+    client: DogStatsD,
+    handle: Option<Handle>,
+    interval: Duration,
+}
+
+impl RuntimeMetricsReporter {
+    pub fn new(client: DogStatsD) -> Self {
+        RuntimeMetricsReporter {
+            client,
+            handle: None,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+
+    /// Sets the runtime to report, the current runtime by default.
+    pub fn with_handle(mut self, handle: Handle) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+
+    /// Sets the sampling interval, 10 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Spawns the reporting task on the reported runtime.
+    ///
+    /// # Panics
+    ///
+    /// Panics if no handle was set and it is not called from a Tokio runtime.
+    pub fn spawn(self) -> JoinHandle<()> {
+        let handle = self.handle.clone().unwrap_or_else(Handle::current);
+        let mut sampler = Sampler::new(self.client, handle.metrics());
+        let period = self.interval;
+        handle.spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            // the first tick completes immediately
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                sampler.sample();
+            }
+        })
+    }
+}
+
+struct Sampler {
+    client: DogStatsD,
+    metrics: RuntimeMetrics,
+    tags: [String; 1],
+    previous: Counters,
+    #[cfg(target_os = "linux")]
+    previous_process: Option<ProcessStats>,
+    last_sample: Instant,
+}
+
+impl Sampler {
+    fn new(client: DogStatsD, metrics: RuntimeMetrics) -> Self {
+        Sampler {
+            client,
+            previous: Counters::read(&metrics),
+            metrics,
+            tags: [format!("{RUNTIME_ID_TAG}:{}", runtime_id())],
+            #[cfg(target_os = "linux")]
+            previous_process: ProcessStats::read().ok(),
+            last_sample: Instant::now(),
+        }
+    }
+
+    fn gauge(&self, name: &str, value: f64) {
+        self.client
+            .gauge(&format!("runtime.rust.{name}"), value, &self.tags());
+    }
+
+    fn count(&self, name: &str, value: u64) {
+        self.client
+            .count(&format!("runtime.rust.{name}"), value as i64, &self.tags());
+    }
+
+    fn tags(&self) -> [&str; 1] {
+        [self.tags[0].as_str()]
+    }
+
+    fn sample(&mut self) {
+        let metrics = &self.metrics;
+        let workers = metrics.num_workers();
+        let counters = Counters::read(metrics);
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_sample);
+
+        self.gauge("tokio.workers", workers as f64);
+        self.gauge("tokio.alive_tasks", metrics.num_alive_tasks() as f64);
+        self.gauge(
+            "tokio.global_queue_depth",
+            metrics.global_queue_depth() as f64,
+        );
+        let busy = counters
+            .busy_duration
+            .saturating_sub(self.previous.busy_duration);
+        let capacity = elapsed.as_secs_f64() * workers as f64;
+        if capacity > 0.0 {
+            self.gauge("tokio.busy_ratio", (busy.as_secs_f64() / capacity).min(1.0));
+        }
+        self.count(
+            "tokio.park_count",
+            counters.park_count.saturating_sub(self.previous.park_count),
+        );
+
+        #[cfg(tokio_unstable)]
+        {
+            self.gauge(
+                "tokio.blocking_threads",
+                metrics.num_blocking_threads() as f64,
+            );
+            self.gauge(
+                "tokio.idle_blocking_threads",
+                metrics.num_idle_blocking_threads() as f64,
+            );
+            self.gauge(
+                "tokio.blocking_queue_depth",
+                metrics.blocking_queue_depth() as f64,
+            );
+            let local_queue_depth: usize = (0..workers)
+                .map(|worker| metrics.worker_local_queue_depth(worker))
+                .sum();
+            self.gauge("tokio.local_queue_depth", local_queue_depth as f64);
+            self.count(
+                "tokio.poll_count",
+                counters.poll_count.saturating_sub(self.previous.poll_count),
+            );
+            self.count(
+                "tokio.steal_count",
+                counters
+                    .steal_count
+                    .saturating_sub(self.previous.steal_count),
+            );
+            self.count(
+                "tokio.spawned_tasks",
+                counters
+                    .spawned_tasks
+                    .saturating_sub(self.previous.spawned_tasks),
+            );
+        }
+
+        #[cfg(target_os = "linux")]
+        self.sample_process();
+
+        self.previous = counters;
+        self.last_sample = now;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sample_process(&mut self) {
+        // the metrics are skipped if `/proc` is not readable, e.g. in some sandboxes
+        let Ok(stats) = ProcessStats::read() else {
+            return;
+        };
+        self.gauge("mem.rss", stats.rss_bytes as f64);
+        self.gauge("mem.virtual", stats.virtual_bytes as f64);
+        self.gauge("thread.count", stats.threads as f64);
+        self.gauge("fd.count", stats.open_fds as f64);
+        if let Some(previous) = &self.previous_process {
+            let user = stats.user_cpu.saturating_sub(previous.user_cpu);
+            let system = stats.system_cpu.saturating_sub(previous.system_cpu);
+            self.gauge("cpu.time.user", user.as_secs_f64());
+            self.gauge("cpu.time.sys", system.as_secs_f64());
+        }
+        self.previous_process = Some(stats);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::tests::{receive, udp_server};
+    use crate::metrics::DogStatsDBuilder;
+
+    #[test]
+    fn test_sample_runtime_metrics() {
+        let (server, url) = udp_server();
+        let statsd = DogStatsDBuilder::new("my-service")
+            .with_url(url)
+            .build()
+            .unwrap();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+
+        let mut sampler = Sampler::new(statsd, runtime.metrics());
+        sampler.sample();
+
+        let tags = format!("|#runtime-id:{},service:my-service", runtime_id());
+        assert_eq!(
+            receive(&server),
+            format!("runtime.rust.tokio.workers:2|g{tags}")
+        );
+        assert_eq!(
+            receive(&server),
+            format!("runtime.rust.tokio.alive_tasks:0|g{tags}")
+        );
+        assert_eq!(
+            receive(&server),
+            format!("runtime.rust.tokio.global_queue_depth:0|g{tags}")
+        );
+        let busy_ratio = receive(&server);
+        assert!(
+            busy_ratio.starts_with("runtime.rust.tokio.busy_ratio:"),
+            "{busy_ratio}"
+        );
+        assert!(receive(&server).starts_with("runtime.rust.tokio.park_count:"));
+
+        #[cfg(target_os = "linux")]
+        for metric in [
+            "mem.rss",
+            "mem.virtual",
+            "thread.count",
+            "fd.count",
+            "cpu.time.user",
+            "cpu.time.sys",
+        ] {
+            let datagram = receive(&server);
+            assert!(
+                datagram.starts_with(&format!("runtime.rust.{metric}:")),
+                "{datagram}"
+            );
+            assert!(datagram.ends_with(&format!("|g{tags}")), "{datagram}");
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Process metrics read from `/proc/self` (Linux only).
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+// `USER_HZ`, the unit of the CPU times of `/proc/<pid>/stat`, is 100 on all Linux platforms
+const CLOCK_TICKS_PER_SECOND: u64 = 100;
+
+/// Snapshot of the process resources.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct ProcessStats {
+    pub(crate) rss_bytes: u64,
+    pub(crate) virtual_bytes: u64,
+    pub(crate) user_cpu: Duration,
+    pub(crate) system_cpu: Duration,
+    pub(crate) threads: u64,
+    pub(crate) open_fds: u64,
+}
+
+impl ProcessStats {
+    pub(crate) fn read() -> io::Result<Self> {
+        let mut stats = parse_stat(&fs::read_to_string("/proc/self/stat")?)?;
+        parse_status(&fs::read_to_string("/proc/self/status")?, &mut stats);
+        stats.open_fds = fs::read_dir("/proc/self/fd")?.count() as u64;
+        Ok(stats)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// see proc(5), the fields are counted from the closing parenthesis of the command name,
+// which can contain spaces and parentheses
+fn parse_stat(stat: &str) -> io::Result<ProcessStats> {
+    let fields = stat
+        .rsplit_once(')')
+        .ok_or_else(|| invalid("malformed /proc/self/stat"))?
+        .1
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    // `state` is field 3 of proc(5), at index 0 here
+    let field = |number: usize| -> io::Result<u64> {
+        fields
+            .get(number - 3)
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| invalid("missing /proc/self/stat field"))
+    };
+    let ticks = |ticks: u64| Duration::from_millis(ticks * 1000 / CLOCK_TICKS_PER_SECOND);
+
+    Ok(ProcessStats {
+        user_cpu: ticks(field(14)?),
+        system_cpu: ticks(field(15)?),
+        threads: field(20)?,
+        virtual_bytes: field(23)?,
+        ..Default::default()
+    })
+}
+
+fn parse_status(status: &str, stats: &mut ProcessStats) {
+    for line in status.lines() {
+        if let Some(rss) = line.strip_prefix("VmRSS:") {
+            let kilobytes = rss.trim().trim_end_matches("kB").trim();
+            stats.rss_bytes = kilobytes.parse::<u64>().unwrap_or_default() * 1024;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "1234 (my (weird) app) S 1 1234 1234 0 -1 4194560 1000 0 0 0 250 130 0 0 20 0 \
+                    7 0 12345 104857600 2560 18446744073709551615 0 0 0 0 0 0 0 0 0 0 0 0 17 3 0 0";
+
+        let stats = parse_stat(stat).unwrap();
+
+        assert_eq!(stats.user_cpu, Duration::from_millis(2500));
+        assert_eq!(stats.system_cpu, Duration::from_millis(1300));
+        assert_eq!(stats.threads, 7);
+        assert_eq!(stats.virtual_bytes, 104857600);
+    }
+
+    #[test]
+    fn test_read_process_stats() {
+        let stats = ProcessStats::read().unwrap();
+
+        assert!(stats.rss_bytes > 0);
+        assert!(stats.virtual_bytes >= stats.rss_bytes);
+        assert!(stats.threads >= 1);
+        assert!(stats.open_fds >= 3);
+    }
+}
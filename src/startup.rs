@@ -0,0 +1,117 @@
+//! Diagnostics logged when the tracer starts.
+//!
+//! As `DD_TRACE_STARTUP_LOGS` does in the official tracers, [`crate::init()`] logs the
+//! configuration of the tracer once, then checks that the agent is reachable on its `/info`
+//! endpoint, so a tracer whose traces never reach Datadog can be diagnosed from the logs. The
+//! `/info` response fetched by the Datadog exporter to discover the agent features is reused.
+use opentelemetry_sdk::runtime::{Runtime, Tokio};
+use serde::Serialize;
+
+use crate::agent::{fetch_info, AgentDiscovery, AgentInfo};
+use crate::exporter::Error;
+
+/// Configuration of the tracer, logged at startup.
+///
+/// See [`crate::tracer::TracerBuilder::startup_configuration`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct StartupConfiguration {
+    pub date: String,
+    pub tracer_version: &'static str,
+    pub lang: &'static str,
+    pub service: String,
+    pub env: Option<String>,
+    pub version: Option<String>,
+    /// Endpoint of the agent, absent for agentless export
+    pub agent_url: Option<String>,
+    pub exporter: String,
+    pub api_version: String,
+    pub sampler: &'static str,
+    pub propagation_styles: Vec<&'static str>,
+    pub stats_computation_enabled: bool,
+}
+
+/// Whether startup logs are enabled by `DD_TRACE_STARTUP_LOGS`, `true` by default.
+pub fn startup_logs_enabled() -> bool {
+    std::env::var("DD_TRACE_STARTUP_LOGS").map_or(true, |s| s != "false" && s != "0")
+}
+
+/// Logs the configuration as a single `DATADOG TRACER CONFIGURATION` event.
+pub fn log_configuration(configuration: &StartupConfiguration) {
+    let configuration = serde_json::to_string(configuration).unwrap_or_default();
+    tracing::info!(configuration, "DATADOG TRACER CONFIGURATION");
+}
+
+/// Queries the agent `/info` endpoint, logging its version and supported endpoints, or a
+/// warning if the agent is unreachable.
+pub async fn check_agent(agent_endpoint: &str) -> Option<AgentInfo> {
+    let info = fetch_info(&reqwest::Client::new(), agent_endpoint).await;
+    log_agent_info(agent_endpoint, info.as_ref())
+}
+
+fn log_agent_info(agent_endpoint: &str, info: Result<&AgentInfo, &Error>) -> Option<AgentInfo> {
+    match info {
+        Ok(info) => {
+            tracing::info!(
+                agent_url = agent_endpoint,
+                agent_version = info.version.as_deref().unwrap_or("unknown"),
+                endpoints = ?info.endpoints,
+                "DATADOG AGENT INFO"
+            );
+            Some(info.clone())
+        }
+        Err(err) => {
+            tracing::warn!(
+                agent_url = agent_endpoint,
+                error = %err,
+                "DATADOG TRACER DIAGNOSTIC - agent unreachable, traces will not be sent"
+            );
+            None
+        }
+    }
+}
+
+/// Logs the configuration, then spawns [`check_agent`] on the Tokio runtime unless exporting
+/// without an agent.
+pub fn log_startup(configuration: &StartupConfiguration) {
+    log_startup_with_discovery(configuration, None);
+}
+
+/// Same as [`log_startup`], logging the result of the agent discovery of the Datadog exporter
+/// instead of querying the agent again.
+pub(crate) fn log_startup_with_discovery(
+    configuration: &StartupConfiguration,
+    discovery: Option<AgentDiscovery>,
+) {
+    log_configuration(configuration);
+    let Some(agent_endpoint) = configuration.agent_url.clone() else {
+        return;
+    };
+    Tokio.spawn(Box::pin(async move {
+        match discovery {
+            Some(discovery) => {
+                let info = discovery.await;
+                log_agent_info(&agent_endpoint, info.as_ref().map_err(|err| &**err));
+            }
+            None => {
+                check_agent(&agent_endpoint).await;
+            }
+        }
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::tests::mock_agent;
+
+    #[tokio::test]
+    async fn test_check_agent() {
+        let endpoint = mock_agent(r#"{"version":"7.50.0","endpoints":["/v0.5/traces"]}"#);
+        let info = check_agent(&endpoint).await.unwrap();
+        assert!(info.supports("/v0.5/traces"));
+
+        // nothing listens on the discard port
+        assert_eq!(check_agent("http://127.0.0.1:9").await, None);
+    }
+}
@@ -0,0 +1,334 @@
+//! Internal telemetry of the tracer.
+//!
+//! The tracer pipeline maintains process-wide counters, to tell whether missing spans were
+//! sampled out, dropped because the export queue was full, or rejected by the agent:
+//!
+//! - spans started, finished, sampled out (finished with a reject sampling decision)
+//!   and dropped (export queue full, failed exports, oversized traces)
+//! - export attempts, errors and payload bytes, the latter only for the payloads encoded by the
+//!   crate: the size of the requests of the OTLP and `opentelemetry_datadog` exporters is unknown
+//! - HTTP status codes returned by the agent or intake
+//!
+//! [`tracer_stats`] returns a [`TracerStats`] snapshot of the counters, and [`TracerStatsReporter`]
+//! periodically logs them at the debug level and, with the `metrics` feature, sends them to
+//! DogStatsD as `datadog.tracer.*` metrics. The reporter is started by [`crate::init`] when
+//! `DD_TRACE_HEALTH_METRICS_ENABLED` is `true`.
+//!
+//! Spans dropped by the export queue are only counted when the OpenTelemetry error handler
+//! [`error_handler`] is used. [`crate::init`] only installs it when the reporter is started, as
+//! it replaces any handler installed by the application, which can call it from its own handler
+//! instead.
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use opentelemetry::global::Error as OtelError;
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::runtime::{Runtime, Tokio, TrySendError};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::DogStatsD;
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Target of the events logged by [`error_handler`].
+pub const ERROR_TARGET: &str = "datadog_tracing::otel";
+
+thread_local! {
+    // whether the current thread is logging an OpenTelemetry error
+    static REPORTING_ERROR: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Counters {
+    spans_started: AtomicU64,
+    spans_finished: AtomicU64,
+    spans_sampled_out: AtomicU64,
+    spans_dropped: AtomicU64,
+    export_attempts: AtomicU64,
+    export_errors: AtomicU64,
+    export_bytes: AtomicU64,
+    http_status_codes: Mutex<BTreeMap<u16, u64>>,
+}
+
+static COUNTERS: Counters = Counters {
+    spans_started: AtomicU64::new(0),
+    spans_finished: AtomicU64::new(0),
+    spans_sampled_out: AtomicU64::new(0),
+    spans_dropped: AtomicU64::new(0),
+    export_attempts: AtomicU64::new(0),
+    export_errors: AtomicU64::new(0),
+    export_bytes: AtomicU64::new(0),
+    http_status_codes: Mutex::new(BTreeMap::new()),
+};
+
+pub(crate) fn span_started() {
+    COUNTERS.spans_started.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn span_finished(sampled: bool) {
+    COUNTERS.spans_finished.fetch_add(1, Ordering::Relaxed);
+    if !sampled {
+        COUNTERS.spans_sampled_out.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn spans_dropped(count: usize) {
+    COUNTERS
+        .spans_dropped
+        .fetch_add(count as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn export_attempt() {
+    COUNTERS.export_attempts.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Records the size of an exported payload, when it is encoded by the crate.
+pub(crate) fn export_bytes(bytes: usize) {
+    COUNTERS
+        .export_bytes
+        .fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn export_error() {
+    COUNTERS.export_errors.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn http_status_code(status: u16) {
+    *COUNTERS
+        .http_status_codes
+        .lock()
+        .unwrap()
+        .entry(status)
+        .or_default() += 1;
+}
+
+/// OpenTelemetry error handler counting the spans dropped by the export queue, and logging
+/// errors as warnings with the [`ERROR_TARGET`] target.
+///
+/// Errors raised while logging an error, e.g. by a layer exporting logs with OpenTelemetry, are
+/// printed to stderr like the default handler instead of being logged again.
+pub fn error_handler(err: OtelError) {
+    if let OtelError::Trace(TraceError::Other(source)) = &err {
+        if let Some(TrySendError::ChannelFull) = source.downcast_ref() {
+            spans_dropped(1);
+        }
+    }
+    if REPORTING_ERROR.replace(true) {
+        eprintln!("OpenTelemetry error occurred. {err}");
+        return;
+    }
+    tracing::warn!(target: ERROR_TARGET, error = %err, "OpenTelemetry error occurred");
+    REPORTING_ERROR.set(false);
+}
+
+/// Reports an error of a log writer with the OpenTelemetry error handler, which prints it to
+/// stderr if it is the [`error_handler`], instead of logging it to the failing writer.
+#[cfg(feature = "logs-intake")]
+pub(crate) fn handle_log_writer_error(err: OtelError) {
+    let reporting = REPORTING_ERROR.replace(true);
+    opentelemetry::global::handle_error(err);
+    REPORTING_ERROR.set(reporting);
+}
+
+/// Snapshot of the tracer counters, since the start of the process.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct TracerStats {
+    pub spans_started: u64,
+    pub spans_finished: u64,
+    pub spans_sampled_out: u64,
+    pub spans_dropped: u64,
+    pub export_attempts: u64,
+    pub export_errors: u64,
+    /// Size of the payloads sent by the Datadog and agentless exporters, not counting the OTLP
+    /// and `opentelemetry_datadog` exports.
+    pub export_bytes: u64,
+    /// Number of responses of the agent or intake, by HTTP status code.
+    pub http_status_codes: BTreeMap<u16, u64>,
+}
+
+/// Returns a snapshot of the tracer counters.
+pub fn tracer_stats() -> TracerStats {
+    TracerStats {
+        spans_started: COUNTERS.spans_started.load(Ordering::Relaxed),
+        spans_finished: COUNTERS.spans_finished.load(Ordering::Relaxed),
+        spans_sampled_out: COUNTERS.spans_sampled_out.load(Ordering::Relaxed),
+        spans_dropped: COUNTERS.spans_dropped.load(Ordering::Relaxed),
+        export_attempts: COUNTERS.export_attempts.load(Ordering::Relaxed),
+        export_errors: COUNTERS.export_errors.load(Ordering::Relaxed),
+        export_bytes: COUNTERS.export_bytes.load(Ordering::Relaxed),
+        http_status_codes: COUNTERS.http_status_codes.lock().unwrap().clone(),
+    }
+}
+
+/// Reporter periodically logging the [`TracerStats`], and sending them to DogStatsD.
+#[derive(Debug, Clone)]
+pub struct TracerStatsReporter {
+    #[cfg(feature = "metrics")]
+    client: Option<DogStatsD>,
+    interval: Duration,
+}
+
+impl Default for TracerStatsReporter {
+    fn default() -> Self {
+        TracerStatsReporter {
+            #[cfg(feature = "metrics")]
+            client: None,
+            interval: DEFAULT_INTERVAL,
+        }
+    }
+}
+
+impl TracerStatsReporter {
+    pub fn new() -> Self {
+        TracerStatsReporter::default()
+    }
+
+    /// Sends the counters to DogStatsD as `datadog.tracer.*` counts.
+    #[cfg(feature = "metrics")]
+    pub fn with_dogstatsd(mut self, client: DogStatsD) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the reporting interval, 10 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Spawns the reporting task on the Tokio runtime.
+    pub fn spawn(self) {
+        Tokio.spawn(Box::pin(async move {
+            let mut previous = TracerStats::default();
+            loop {
+                Tokio.delay(self.interval).await;
+                let stats = tracer_stats();
+                self.report(&previous, &stats);
+                previous = stats;
+            }
+        }));
+    }
+
+    fn report(&self, previous: &TracerStats, stats: &TracerStats) {
+        tracing::debug!(
+            spans_started = stats.spans_started,
+            spans_finished = stats.spans_finished,
+            spans_sampled_out = stats.spans_sampled_out,
+            spans_dropped = stats.spans_dropped,
+            export_attempts = stats.export_attempts,
+            export_errors = stats.export_errors,
+            export_bytes = stats.export_bytes,
+            http_status_codes = ?stats.http_status_codes,
+            "datadog tracer stats"
+        );
+
+        #[cfg(feature = "metrics")]
+        if let Some(client) = &self.client {
+            let counts = [
+                ("spans.started", stats.spans_started, previous.spans_started),
+                (
+                    "spans.finished",
+                    stats.spans_finished,
+                    previous.spans_finished,
+                ),
+                (
+                    "spans.sampled_out",
+                    stats.spans_sampled_out,
+                    previous.spans_sampled_out,
+                ),
+                ("spans.dropped", stats.spans_dropped, previous.spans_dropped),
+                (
+                    "export.attempts",
+                    stats.export_attempts,
+                    previous.export_attempts,
+                ),
+                ("export.errors", stats.export_errors, previous.export_errors),
+                ("export.bytes", stats.export_bytes, previous.export_bytes),
+            ];
+            for (name, value, previous) in counts {
+                client.count(
+                    &format!("datadog.tracer.{name}"),
+                    value.saturating_sub(previous) as i64,
+                    &[],
+                );
+            }
+            for (status, count) in &stats.http_status_codes {
+                let previous = previous.http_status_codes.get(status).copied();
+                client.count(
+                    "datadog.tracer.api.responses",
+                    count.saturating_sub(previous.unwrap_or_default()) as i64,
+                    &[format!("status_code:{status}").as_str()],
+                );
+            }
+        }
+        #[cfg(not(feature = "metrics"))]
+        let _ = previous;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the counters are shared with the other tests, running concurrently
+    #[test]
+    fn test_tracer_stats() {
+        let before = tracer_stats();
+
+        span_started();
+        span_finished(false);
+        export_attempt();
+        export_bytes(100);
+        export_error();
+        http_status_code(503);
+        error_handler(OtelError::Trace(TraceError::Other(
+            TrySendError::ChannelFull.into(),
+        )));
+
+        let after = tracer_stats();
+        assert!(after.spans_started > before.spans_started);
+        assert!(after.spans_sampled_out > before.spans_sampled_out);
+        assert!(after.spans_dropped > before.spans_dropped);
+        assert!(after.export_errors > before.export_errors);
+        assert!(after.export_bytes >= before.export_bytes + 100);
+        assert!(
+            after.http_status_codes[&503]
+                > before
+                    .http_status_codes
+                    .get(&503)
+                    .copied()
+                    .unwrap_or_default()
+        );
+    }
+
+    #[test]
+    fn test_error_handler_reentrancy() {
+        use std::sync::atomic::AtomicUsize;
+        use std::sync::Arc;
+        use tracing_subscriber::layer::{Context, SubscriberExt};
+        use tracing_subscriber::Layer;
+
+        // a layer failing to handle the error events
+        struct FailingLayer(Arc<AtomicUsize>);
+
+        impl<S: tracing::Subscriber> Layer<S> for FailingLayer {
+            fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+                assert_eq!(event.metadata().target(), ERROR_TARGET);
+                self.0.fetch_add(1, Ordering::Relaxed);
+                error_handler(OtelError::Other("failed to log".to_string()));
+            }
+        }
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(FailingLayer(events.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            error_handler(OtelError::Other("export failed".to_string()));
+            error_handler(OtelError::Other("export failed".to_string()));
+        });
+
+        assert_eq!(events.load(Ordering::Relaxed), 2);
+    }
+}
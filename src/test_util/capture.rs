@@ -0,0 +1,252 @@
+//! In-memory capture of spans and logs.
+//!
+//! Unlike a tracer built by [`crate::tracer::TracerBuilder`], nothing is batched nor sent over
+//! the network: spans are recorded as soon as they end, and logs are formatted in a buffer.
+//!
+//! ```
+//! use datadog_tracing::test_util::Capture;
+//!
+//! let capture = Capture::new("my-service");
+//! capture.in_scope(|| {
+//!     let _span = tracing::info_span!("handle_request").entered();
+//!     tracing::info!("done");
+//! });
+//!
+//! let traces = capture.traces();
+//! assert_eq!(traces[0].root().resource, "handle_request");
+//! let logs = capture.logs();
+//! assert_eq!(logs[0]["message"], "done");
+//! assert_eq!(logs[0]["dd.trace_id"], traces[0].trace_id());
+//! ```
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use opentelemetry::trace::{TraceResult, TracerProvider as _};
+use opentelemetry::{global, Context};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::trace::{Span, SpanProcessor, Tracer, TracerProvider};
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use super::{AgentSpan, Trace};
+use crate::exporter::{group_into_traces, DatadogSpan, ModelConfig};
+use crate::formatter::LogFormat;
+use crate::mapping::Mapping;
+use crate::propagator::DatadogPropagator;
+use crate::tracer::local_provider_builder;
+
+/// Spans and logs captured in memory, see the [module documentation](self).
+///
+/// The subscriber is scoped to a test with [`Capture::in_scope`], or
+/// [`tracing::subscriber::set_default`] with [`Capture::subscriber`] for async tests, instead of
+/// being installed globally.
+pub struct Capture {
+    tracer: Tracer,
+    // the tracer only holds a weak reference to its provider
+    _provider: TracerProvider,
+    spans: Arc<Mutex<Vec<SpanData>>>,
+    logs: LogBuffer,
+    model_config: ModelConfig,
+    mapping: Mapping,
+}
+
+impl Debug for Capture {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Capture")
+            .field("model_config", &self.model_config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Capture {
+    /// Creates a capture whose spans are tagged with the given service name.
+    ///
+    /// Nothing is installed globally, see [`Capture::install_propagator`] to test the
+    /// propagation of the trace context.
+    pub fn new<T: Into<String>>(service_name: T) -> Self {
+        let spans = Arc::<Mutex<Vec<SpanData>>>::default();
+        let provider = local_provider_builder()
+            .with_span_processor(InMemoryProcessor(spans.clone()))
+            .build();
+
+        Capture {
+            tracer: provider.tracer("opentelemetry-datadog"),
+            _provider: provider,
+            spans,
+            logs: LogBuffer::default(),
+            model_config: ModelConfig {
+                service: service_name.into(),
+                ..Default::default()
+            },
+            mapping: Mapping::default(),
+        }
+    }
+
+    /// Installs the Datadog propagator globally, like [`crate::init()`].
+    ///
+    /// The propagator is shared by the whole process, including the tests running concurrently.
+    pub fn install_propagator(&self) {
+        global::set_text_map_propagator(DatadogPropagator::default());
+    }
+
+    /// The subscriber with the log and OpenTelemetry layers of [`crate::init()`], writing to
+    /// this capture.
+    pub fn subscriber(&self) -> impl Subscriber + Send + Sync + 'static {
+        Registry::default()
+            .with(crate::init::log_layer(
+                LogFormat::Json,
+                true,
+                false,
+                self.logs.clone(),
+            ))
+            .with(tracing_opentelemetry::layer().with_tracer(self.tracer.clone()))
+    }
+
+    /// Runs `f` with the subscriber of this capture as the default one.
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        tracing::subscriber::with_default(self.subscriber(), f)
+    }
+
+    /// The finished spans, with the Datadog fields sent by the exporter, grouped by trace.
+    pub fn traces(&self) -> Vec<Trace> {
+        let spans = self.spans.lock().unwrap();
+        group_into_traces(&spans)
+            .into_iter()
+            .map(|trace| Trace {
+                spans: trace
+                    .into_iter()
+                    .map(|span| {
+                        AgentSpan::from(&DatadogSpan::new(span, &self.model_config, &self.mapping))
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The finished spans, as recorded by OpenTelemetry.
+    pub fn span_data(&self) -> Vec<SpanData> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    /// The formatted log lines.
+    pub fn log_lines(&self) -> Vec<String> {
+        let logs = self.logs.0.lock().unwrap();
+        String::from_utf8_lossy(&logs)
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// The log records, parsed from the JSON log lines.
+    pub fn logs(&self) -> Vec<serde_json::Value> {
+        self.log_lines()
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap_or_else(|_| line.as_str().into()))
+            .collect()
+    }
+}
+
+impl From<&DatadogSpan<'_>> for AgentSpan {
+    fn from(span: &DatadogSpan<'_>) -> Self {
+        AgentSpan {
+            service: span.service.to_string(),
+            name: span.name.to_string(),
+            resource: span.resource.to_string(),
+            trace_id: span.trace_id,
+            span_id: span.span_id,
+            parent_id: span.parent_id,
+            start: span.start,
+            duration: span.duration,
+            error: span.error,
+            meta: span
+                .meta
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            metrics: span
+                .metrics
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect::<HashMap<_, _>>(),
+            r#type: span.r#type.to_string(),
+        }
+    }
+}
+
+// records spans as soon as they end, unlike the simple span processor exporting them from
+// a background thread
+#[derive(Debug)]
+struct InMemoryProcessor(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for InMemoryProcessor {
+    fn on_start(&self, _span: &mut Span, _cx: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> TraceResult<()> {
+        Ok(())
+    }
+
+    fn shutdown(&mut self) -> TraceResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogBuffer {
+    type Writer = LogBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let capture = Capture::new("my-service");
+
+        capture.in_scope(|| {
+            let _parent = tracing::info_span!("parent").entered();
+            let _child = tracing::info_span!("child", user_id = 42).entered();
+            tracing::info!(order_id = 7, "created");
+        });
+
+        let traces = capture.traces();
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        trace.assert_child_of("child", "parent");
+        assert_eq!(trace.span("child").service, "my-service");
+        assert_eq!(trace.span("child").metric("user_id"), Some(42.0));
+        assert_eq!(capture.span_data().len(), 2);
+
+        let logs = capture.logs();
+        assert_eq!(logs.len(), 1);
+        assert_eq!(logs[0]["message"], "created");
+        assert_eq!(logs[0]["order_id"], 7);
+        assert_eq!(logs[0]["dd.trace_id"], trace.trace_id());
+        assert_eq!(logs[0]["dd.span_id"], trace.span("child").span_id);
+    }
+}
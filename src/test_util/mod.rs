@@ -0,0 +1,544 @@
+//! Utilities to test instrumented services.
+//!
+//! [`Capture`] records the spans and logs of unit tests in memory, with the same layers as
+//! [`crate::init()`], see its documentation.
+//!
+//! [`FakeAgent`] is an in-process HTTP server implementing the agent `/v0.4/traces`,
+//! `/v0.5/traces` and `/info` endpoints. The received payloads are decoded into [`Trace`]s, so
+//! tests of instrumented services can assert on what is sent to Datadog.
+//!
+//! ```no_run
+//! use datadog_tracing::test_util::FakeAgent;
+//! use datadog_tracing::tracer::TracerBuilder;
+//! use opentelemetry::trace::Tracer;
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), opentelemetry::trace::TraceError> {
+//! let agent = FakeAgent::start();
+//! let tracer = TracerBuilder::new("my-service")
+//!     .with_agent_endpoint(agent.endpoint())
+//!     .build()?;
+//!
+//! tracer.in_span("GET /users/{id}", |_| tracer.in_span("SELECT users", |_| {}));
+//! // flushes the spans
+//! datadog_tracing::shutdown_tracer_provider();
+//!
+//! let traces = agent.wait_for_traces(1).await;
+//! traces[0].assert_child_of("SELECT users", "GET /users/{id}");
+//! traces[0].assert_tag("GET /users/{id}", "span.kind", "internal");
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The batch exporter blocks on flush and shutdown, so tests must use a multi-threaded runtime,
+//! e.g. `#[tokio::test(flavor = "multi_thread")]`.
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use rmpv::Value;
+use tokio::sync::{oneshot, Notify};
+
+use crate::agent::INFO_PATH;
+
+mod capture;
+
+pub use capture::Capture;
+
+/// Time waited by [`FakeAgent::wait_for_traces`] before panicking.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const DEFAULT_ENDPOINTS: [&str; 3] = ["/v0.4/traces", "/v0.5/traces", "/v0.6/stats"];
+
+/// A span received by the [`FakeAgent`], or captured by [`Capture`].
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct AgentSpan {
+    pub service: String,
+    pub name: String,
+    pub resource: String,
+    pub trace_id: u64,
+    pub span_id: u64,
+    pub parent_id: u64,
+    pub start: i64,
+    pub duration: i64,
+    pub error: i32,
+    pub meta: HashMap<String, String>,
+    pub metrics: HashMap<String, f64>,
+    pub r#type: String,
+}
+
+impl AgentSpan {
+    pub fn meta(&self, key: &str) -> Option<&str> {
+        self.meta.get(key).map(String::as_str)
+    }
+
+    pub fn metric(&self, key: &str) -> Option<f64> {
+        self.metrics.get(key).copied()
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error != 0
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        self.name == name || self.resource == name
+    }
+}
+
+/// The spans of a trace received by the [`FakeAgent`] or captured by [`Capture`], in the order
+/// they were sent.
+///
+/// Spans are looked up by operation name or resource, and the lookups and assertions panic
+/// with the spans of the trace when no span matches.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Trace {
+    pub spans: Vec<AgentSpan>,
+}
+
+impl Trace {
+    pub fn trace_id(&self) -> u64 {
+        self.spans
+            .first()
+            .map(|span| span.trace_id)
+            .unwrap_or_default()
+    }
+
+    /// The local root span, whose parent is not part of the trace.
+    pub fn root(&self) -> &AgentSpan {
+        self.spans
+            .iter()
+            .find(|span| !self.spans.iter().any(|s| s.span_id == span.parent_id))
+            .unwrap_or_else(|| panic!("no root span in {}", self.describe()))
+    }
+
+    pub fn span(&self, name: &str) -> &AgentSpan {
+        self.spans
+            .iter()
+            .find(|span| span.matches(name))
+            .unwrap_or_else(|| panic!("no span {name} in {}", self.describe()))
+    }
+
+    pub fn children(&self, parent: &AgentSpan) -> Vec<&AgentSpan> {
+        self.spans
+            .iter()
+            .filter(|span| span.parent_id == parent.span_id)
+            .collect()
+    }
+
+    #[track_caller]
+    pub fn assert_child_of(&self, child: &str, parent: &str) {
+        let (child_span, parent_span) = (self.span(child), self.span(parent));
+        assert_eq!(
+            child_span.parent_id,
+            parent_span.span_id,
+            "{child} is not a child of {parent} in {}",
+            self.describe()
+        );
+    }
+
+    #[track_caller]
+    pub fn assert_tag(&self, span: &str, key: &str, value: &str) {
+        assert_eq!(
+            self.span(span).meta(key),
+            Some(value),
+            "unexpected {key} tag of {span}"
+        );
+    }
+
+    fn describe(&self) -> String {
+        let spans: Vec<_> = self
+            .spans
+            .iter()
+            .map(|span| {
+                format!(
+                    "{} ({}, span_id {}, parent_id {})",
+                    span.name, span.resource, span.span_id, span.parent_id
+                )
+            })
+            .collect();
+        format!("trace {} [{}]", self.trace_id(), spans.join(", "))
+    }
+}
+
+#[derive(Default)]
+struct State {
+    traces: Mutex<Vec<Trace>>,
+    received: Notify,
+    stats_payloads: AtomicUsize,
+    info: String,
+    endpoints: Vec<String>,
+}
+
+/// In-process fake of the Datadog agent, see the [module documentation](self).
+///
+/// The server is stopped when the agent is dropped.
+pub struct FakeAgent {
+    addr: SocketAddr,
+    state: Arc<State>,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl Debug for FakeAgent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeAgent")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FakeAgent {
+    /// Starts an agent supporting the v0.4 and v0.5 trace APIs and client side stats.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a Tokio runtime.
+    pub fn start() -> Self {
+        Self::start_with_endpoints(&DEFAULT_ENDPOINTS)
+    }
+
+    /// Starts an agent supporting only the given endpoints, listed on `/info`, e.g.
+    /// `["/v0.4/traces"]` to test an older agent. Other endpoints answer `404 Not Found`.
+    ///
+    /// # Panics
+    ///
+    /// Outside of a Tokio runtime.
+    pub fn start_with_endpoints(endpoints: &[&str]) -> Self {
+        let info = serde_json::json!({
+            "version": "fake",
+            "endpoints": endpoints,
+            "client_drop_p0s": true,
+        });
+        let state = Arc::new(State {
+            info: info.to_string(),
+            endpoints: endpoints
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect(),
+            ..Default::default()
+        });
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let state = state.clone();
+                    async move { Ok::<_, Infallible>(handle(&state, request).await) }
+                }))
+            }
+        });
+        let (shutdown, stopped) = oneshot::channel();
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            let _ = stopped.await;
+        }));
+
+        FakeAgent {
+            addr,
+            state,
+            _shutdown: shutdown,
+        }
+    }
+
+    /// Endpoint of the agent, to configure with
+    /// [`TracerBuilder::with_agent_endpoint`](crate::tracer::TracerBuilder::with_agent_endpoint).
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The traces received so far.
+    pub fn traces(&self) -> Vec<Trace> {
+        self.state.traces.lock().unwrap().clone()
+    }
+
+    /// The spans of all the traces received so far.
+    pub fn spans(&self) -> Vec<AgentSpan> {
+        self.traces()
+            .into_iter()
+            .flat_map(|trace| trace.spans)
+            .collect()
+    }
+
+    /// The number of stats payloads received on `/v0.6/stats`.
+    pub fn stats_payloads(&self) -> usize {
+        self.state.stats_payloads.load(Ordering::Relaxed)
+    }
+
+    /// Waits until at least `count` traces are received, and returns them.
+    ///
+    /// # Panics
+    ///
+    /// If they are not received within 10 seconds.
+    pub async fn wait_for_traces(&self, count: usize) -> Vec<Trace> {
+        let wait = async {
+            loop {
+                let received = self.state.received.notified();
+                let traces = self.traces();
+                if traces.len() >= count {
+                    return traces;
+                }
+                received.await;
+            }
+        };
+        match tokio::time::timeout(WAIT_TIMEOUT, wait).await {
+            Ok(traces) => traces,
+            Err(_) => panic!(
+                "expected {count} traces, received {}",
+                self.state.traces.lock().unwrap().len()
+            ),
+        }
+    }
+}
+
+async fn handle(state: &State, request: Request<Body>) -> Response<Body> {
+    let (method, path) = (request.method().clone(), request.uri().path().to_string());
+    match (method, path.as_str()) {
+        (Method::GET, INFO_PATH) => Response::new(Body::from(state.info.clone())),
+        (_, path) if path != INFO_PATH && !state.endpoints.iter().any(|e| e == path) => {
+            response(StatusCode::NOT_FOUND, "404 page not found".to_string())
+        }
+        (Method::POST | Method::PUT, "/v0.4/traces" | "/v0.5/traces") => {
+            let body = match hyper::body::to_bytes(request.into_body()).await {
+                Ok(body) => body,
+                Err(err) => return response(StatusCode::BAD_REQUEST, err.to_string()),
+            };
+            let decoded = rmpv::decode::read_value(&mut body.as_ref())
+                .map_err(|err| err.to_string())
+                .and_then(|payload| match path.as_str() {
+                    "/v0.4/traces" => decode_v04(&payload),
+                    _ => decode_v05(&payload),
+                });
+            match decoded {
+                Ok(traces) => {
+                    state.traces.lock().unwrap().extend(traces);
+                    state.received.notify_waiters();
+                    response(StatusCode::OK, r#"{"rate_by_service":{}}"#.to_string())
+                }
+                Err(message) => response(StatusCode::BAD_REQUEST, message),
+            }
+        }
+        (Method::POST | Method::PUT, "/v0.6/stats") => {
+            state.stats_payloads.fetch_add(1, Ordering::Relaxed);
+            response(StatusCode::OK, String::new())
+        }
+        _ => response(StatusCode::NOT_FOUND, "404 page not found".to_string()),
+    }
+}
+
+fn response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+// the payload is an array of traces, each an array of spans encoded as maps
+fn decode_v04(payload: &Value) -> Result<Vec<Trace>, String> {
+    decode_traces(payload, |span| {
+        let fields = span.as_map().ok_or("span is not a map")?;
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(name))
+                .map(|(_, v)| v)
+                .unwrap_or(&Value::Nil)
+        };
+        let string = |name: &str| field(name).as_str().unwrap_or_default().to_string();
+        Ok(AgentSpan {
+            service: string("service"),
+            name: string("name"),
+            resource: string("resource"),
+            trace_id: field("trace_id").as_u64().unwrap_or_default(),
+            span_id: field("span_id").as_u64().unwrap_or_default(),
+            parent_id: field("parent_id").as_u64().unwrap_or_default(),
+            start: field("start").as_i64().unwrap_or_default(),
+            duration: field("duration").as_i64().unwrap_or_default(),
+            error: field("error").as_i64().unwrap_or_default() as i32,
+            meta: decode_map(field("meta"), |v| v.as_str().map(str::to_string))?,
+            metrics: decode_map(field("metrics"), Value::as_f64)?,
+            r#type: string("type"),
+        })
+    })
+}
+
+// the payload is a dictionary of strings and an array of traces, each an array of spans
+// encoded as arrays of 12 elements referencing the dictionary
+fn decode_v05(payload: &Value) -> Result<Vec<Trace>, String> {
+    let dictionary = payload[0].as_array().ok_or("missing dictionary")?;
+    let string = |value: &Value| -> Result<String, String> {
+        value
+            .as_u64()
+            .and_then(|index| dictionary.get(index as usize))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| format!("invalid string reference {value}"))
+    };
+    decode_traces(&payload[1], |span| {
+        let fields = span.as_array().filter(|fields| fields.len() == 12);
+        let fields = fields.ok_or("span is not an array of 12 elements")?;
+        let mut meta = HashMap::new();
+        for (key, value) in fields[9].as_map().ok_or("invalid meta")? {
+            meta.insert(string(key)?, string(value)?);
+        }
+        let mut metrics = HashMap::new();
+        for (key, value) in fields[10].as_map().ok_or("invalid metrics")? {
+            metrics.insert(string(key)?, value.as_f64().ok_or("invalid metric")?);
+        }
+        Ok(AgentSpan {
+            service: string(&fields[0])?,
+            name: string(&fields[1])?,
+            resource: string(&fields[2])?,
+            trace_id: fields[3].as_u64().unwrap_or_default(),
+            span_id: fields[4].as_u64().unwrap_or_default(),
+            parent_id: fields[5].as_u64().unwrap_or_default(),
+            start: fields[6].as_i64().unwrap_or_default(),
+            duration: fields[7].as_i64().unwrap_or_default(),
+            error: fields[8].as_i64().unwrap_or_default() as i32,
+            meta,
+            metrics,
+            r#type: string(&fields[11])?,
+        })
+    })
+}
+
+fn decode_traces<F>(traces: &Value, decode_span: F) -> Result<Vec<Trace>, String>
+where
+    F: Fn(&Value) -> Result<AgentSpan, String>,
+{
+    traces
+        .as_array()
+        .ok_or("traces are not an array")?
+        .iter()
+        .map(|trace| {
+            let spans = trace.as_array().ok_or("trace is not an array")?;
+            let spans = spans.iter().map(&decode_span).collect::<Result<_, _>>()?;
+            Ok(Trace { spans })
+        })
+        .collect()
+}
+
+fn decode_map<T, F>(value: &Value, decode: F) -> Result<HashMap<String, T>, String>
+where
+    F: Fn(&Value) -> Option<T>,
+{
+    let Some(entries) = value.as_map() else {
+        return Ok(HashMap::new());
+    };
+    entries
+        .iter()
+        .map(|(key, value)| match (key.as_str(), decode(value)) {
+            (Some(key), Some(value)) => Ok((key.to_string(), value)),
+            _ => Err(format!("invalid map entry {key}: {value}")),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::{discover, fetch_info, AgentFeatures};
+    use crate::exporter::{ApiVersion, DatadogExporter, ModelConfig};
+    use crate::fixtures::span_with_ids;
+    use crate::mapping::Mapping;
+    use opentelemetry::trace::SpanKind;
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::export::trace::{SpanData, SpanExporter};
+    use std::sync::RwLock;
+
+    fn span_with_parent(name: &'static str, span_id: u64, parent_id: u64) -> SpanData {
+        SpanData {
+            name: name.into(),
+            span_kind: SpanKind::Server,
+            attributes: vec![KeyValue::new("user.id", "42")],
+            ..span_with_ids(1, span_id, parent_id)
+        }
+    }
+
+    async fn export(agent: &FakeAgent, api_version: ApiVersion) {
+        let features = AgentFeatures::resolve(Some(api_version), false, None);
+        let mut exporter = DatadogExporter::new(
+            reqwest::Client::new(),
+            &agent.endpoint(),
+            Arc::new(RwLock::new(features)),
+            ModelConfig {
+                service: "my-service".to_string(),
+                ..Default::default()
+            },
+            Mapping::default(),
+        );
+        let batch = vec![
+            span_with_parent("GET /users", 1, 0),
+            span_with_parent("SELECT users", 2, 1),
+        ];
+        exporter.export(batch).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_receive_traces() {
+        for api_version in [ApiVersion::Version04, ApiVersion::Version05] {
+            let agent = FakeAgent::start();
+            export(&agent, api_version).await;
+
+            let traces = agent.wait_for_traces(1).await;
+
+            assert_eq!(traces.len(), 1);
+            let trace = &traces[0];
+            assert_eq!(trace.trace_id(), 1);
+            assert_eq!(trace.root().resource, "GET /users");
+            assert_eq!(trace.span("SELECT users").service, "my-service");
+            assert_eq!(trace.children(trace.root()).len(), 1);
+            trace.assert_child_of("SELECT users", "GET /users");
+            trace.assert_tag("GET /users", "user.id", "42");
+            assert_eq!(agent.spans().len(), 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_info() {
+        let agent = FakeAgent::start_with_endpoints(&["/v0.4/traces"]);
+
+        let info = fetch_info(&reqwest::Client::new(), &agent.endpoint())
+            .await
+            .unwrap();
+
+        assert_eq!(info.trace_api_version(), Some(ApiVersion::Version04));
+    }
+
+    #[tokio::test]
+    async fn test_first_export_waits_for_discovery() {
+        let agent = FakeAgent::start_with_endpoints(&["/v0.4/traces"]);
+        // v0.5 until the agent is discovered
+        let features = Arc::new(RwLock::new(AgentFeatures::resolve(None, false, None)));
+        let client = reqwest::Client::new();
+        let discovery = discover(
+            client.clone(),
+            agent.endpoint(),
+            None,
+            false,
+            features.clone(),
+        );
+        let mut exporter = DatadogExporter::new(
+            client,
+            &agent.endpoint(),
+            features,
+            ModelConfig::default(),
+            Mapping::default(),
+        )
+        .with_discovery(discovery);
+
+        // exported right away, before the discovery completes
+        exporter
+            .export(vec![span_with_parent("GET /users", 1, 0)])
+            .await
+            .unwrap();
+
+        assert_eq!(agent.traces().len(), 1);
+    }
+}
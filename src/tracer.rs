@@ -1,53 +1,538 @@
 //! Trace and layer builders to export traces to the Datadog agent.
 //!
-//! This module contains a function that builds a tracer with an exporter
-//! to send traces to the Datadog agent in batches over gRPC.
+//! This module contains a builder for a tracer with an exporter
+//! to send traces to the Datadog agent in batches over HTTP.
+//! By default, spans are exported using the v0.5 agent API by the
+//! [`crate::exporter::DatadogExporter`].
 //!
-//! It also contains a convenience function to build a layer with the tracer.
+//! It also contains convenience functions to build a tracer or a layer
+//! configured from the environment.
+use futures_util::FutureExt;
 use opentelemetry::global;
+use opentelemetry::trace::TracerProvider as _;
 pub use opentelemetry::trace::{TraceError, TraceId, TraceResult};
-use opentelemetry_datadog::{ApiVersion, DatadogPropagator};
+use opentelemetry_sdk::export::trace::SpanData;
+use opentelemetry_sdk::runtime::Runtime;
 use opentelemetry_sdk::trace;
-use opentelemetry_sdk::trace::{RandomIdGenerator, Sampler, Tracer};
+use opentelemetry_sdk::trace::{
+    BatchSpanProcessor, RandomIdGenerator, Sampler, Tracer, TracerProvider,
+};
+use opentelemetry_sdk::Resource;
+use std::borrow::Cow;
 use std::env;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tracing::Subscriber;
 use tracing_opentelemetry::{OpenTelemetryLayer, PreSampledTracer};
 use tracing_subscriber::registry::LookupSpan;
 
-pub fn build_tracer() -> TraceResult<Tracer> {
-    let service_name = env::var("DD_SERVICE")
-        .map_err(|_| <&str as Into<TraceError>>::into("missing DD_SERVICE"))?;
+use crate::agent::{self, AgentDiscovery, AgentFeatures, SharedFeatures};
+#[cfg(feature = "agentless")]
+use crate::exporter::agentless::{intake_url, AgentlessExporter, DEFAULT_EXPORT_TIMEOUT};
+use crate::exporter::stats::StatsProcessor;
+pub use crate::exporter::ApiVersion;
+use crate::exporter::{DatadogExporter, ModelConfig};
+use crate::mapping::{attribute, Mapping, MappingExporter, OPERATION_NAME_KEY, RESOURCE_NAME_KEY};
+#[cfg(feature = "otlp")]
+pub use crate::otlp::OtlpProtocol;
+use crate::processor::DatadogSpanProcessor;
+use crate::propagator::DatadogPropagator;
+use crate::startup::StartupConfiguration;
+
+/// Exporter used to send spans to Datadog.
+///
+/// Variants are added by the `otlp` and `agentless` features, so matches need a wildcard arm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TraceExporter {
+    /// The exporter of this crate, see [`crate::exporter`].
+    #[default]
+    Datadog,
+    /// The exporter provided by `opentelemetry_datadog`, always using the v0.5 API.
+    OpentelemetryDatadog,
+    /// OTLP export to the agent's OTLP intake, see [`crate::otlp`].
+    #[cfg(feature = "otlp")]
+    Otlp(OtlpProtocol),
+    /// Direct export to the Datadog intake, without an agent, see [`crate::exporter::agentless`].
+    #[cfg(feature = "agentless")]
+    Agentless,
+}
+
+impl FromStr for TraceExporter {
+    type Err = TraceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "datadog" => Ok(TraceExporter::Datadog),
+            "opentelemetry-datadog" => Ok(TraceExporter::OpentelemetryDatadog),
+            #[cfg(feature = "otlp")]
+            "otlp" => Ok(TraceExporter::Otlp(OtlpProtocol::default())),
+            #[cfg(feature = "agentless")]
+            "agentless" => Ok(TraceExporter::Agentless),
+            _ => Err(format!("unsupported trace exporter {s}").into()),
+        }
+    }
+}
+
+impl Display for TraceExporter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceExporter::Datadog => write!(f, "datadog"),
+            TraceExporter::OpentelemetryDatadog => write!(f, "opentelemetry-datadog"),
+            #[cfg(feature = "otlp")]
+            TraceExporter::Otlp(_) => write!(f, "otlp"),
+            #[cfg(feature = "agentless")]
+            TraceExporter::Agentless => write!(f, "agentless"),
+        }
+    }
+}
+
+/// Settings of the [`TraceExporter::Agentless`] exporter.
+#[cfg(feature = "agentless")]
+#[derive(Debug, Clone)]
+struct AgentlessConfig {
+    api_key: Option<String>,
+    site: String,
+    url: Option<String>,
+}
+
+#[cfg(feature = "agentless")]
+impl Default for AgentlessConfig {
+    fn default() -> Self {
+        AgentlessConfig {
+            api_key: None,
+            site: "datadoghq.com".to_string(),
+            url: None,
+        }
+    }
+}
+
+/// Builder for a [`Tracer`] exporting to the Datadog agent.
+///
+/// ```no_run
+/// use std::borrow::Cow;
+/// use datadog_tracing::tracer::TracerBuilder;
+///
+/// # fn main() -> Result<(), opentelemetry::trace::TraceError> {
+/// let tracer = TracerBuilder::from_env()?
+///     .with_resource_mapping(|span| Cow::Owned(span.name.to_lowercase()))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TracerBuilder {
+    service_name: String,
+    env: Option<String>,
+    version: Option<String>,
+    agent_endpoint: String,
+    #[cfg(feature = "otlp")]
+    otlp_endpoint: Option<String>,
+    #[cfg(feature = "agentless")]
+    agentless: AgentlessConfig,
+    api_version: Option<ApiVersion>,
+    exporter: TraceExporter,
+    mapping: Mapping,
+    stats_computation: bool,
+}
+
+impl TracerBuilder {
+    pub fn new<T: Into<String>>(service_name: T) -> Self {
+        TracerBuilder {
+            service_name: service_name.into(),
+            env: None,
+            version: None,
+            agent_endpoint: "http://localhost:8126".to_string(),
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "agentless")]
+            agentless: AgentlessConfig::default(),
+            api_version: None,
+            exporter: TraceExporter::default(),
+            mapping: Mapping::default(),
+            stats_computation: false,
+        }
+    }
+
+    /// Creates a builder configured from the `DD_SERVICE`, `DD_ENV`, `DD_VERSION`,
+    /// `DD_AGENT_HOST`, `DD_AGENT_PORT`, `DD_TRACE_API_VERSION`, `DD_TRACE_EXPORTER` and
+    /// `DD_TRACE_STATS_COMPUTATION_ENABLED` environment variables.
+    ///
+    /// The agentless exporter also reads `DD_API_KEY`, `DD_SITE` and `DD_TRACE_AGENTLESS_URL`.
+    pub fn from_env() -> TraceResult<Self> {
+        let service_name = env::var("DD_SERVICE")
+            .map_err(|_| <&str as Into<TraceError>>::into("missing DD_SERVICE"))?;
+
+        let dd_host = env::var("DD_AGENT_HOST").unwrap_or("localhost".to_string());
+        let dd_port = env::var("DD_AGENT_PORT")
+            .ok()
+            .and_then(|it| it.parse::<i32>().ok())
+            .unwrap_or(8126);
+
+        let mut builder =
+            Self::new(service_name).with_agent_endpoint(format!("http://{dd_host}:{dd_port}"));
+        builder.env = env::var("DD_ENV").ok();
+        builder.version = env::var("DD_VERSION").ok();
+        if let Ok(api_version) = env::var("DD_TRACE_API_VERSION") {
+            builder.api_version = Some(api_version.parse()?);
+        }
+        builder.stats_computation = env::var("DD_TRACE_STATS_COMPUTATION_ENABLED")
+            .map(|s| s == "true")
+            .unwrap_or(false);
+        if let Ok(exporter) = env::var("DD_TRACE_EXPORTER") {
+            builder.exporter = exporter.parse()?;
+        }
+        #[cfg(feature = "otlp")]
+        if let TraceExporter::Otlp(protocol) = &mut builder.exporter {
+            if let Ok(otlp_protocol) = env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+                *protocol = otlp_protocol.parse()?;
+            }
+            builder.otlp_endpoint = Some(
+                env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                    .unwrap_or_else(|_| format!("http://{dd_host}:{}", protocol.default_port())),
+            );
+        }
+
+        #[cfg(feature = "agentless")]
+        if builder.exporter == TraceExporter::Agentless {
+            builder.agentless.api_key = env::var("DD_API_KEY").ok();
+            if let Ok(site) = env::var("DD_SITE") {
+                builder.agentless.site = site;
+            }
+            builder.agentless.url = env::var("DD_TRACE_AGENTLESS_URL").ok();
+        }
+
+        Ok(builder)
+    }
+
+    /// Sets the `env` tag added to every span.
+    pub fn with_env<T: Into<String>>(mut self, env: T) -> Self {
+        self.env = Some(env.into());
+        self
+    }
+
+    /// Sets the `version` tag added to every span.
+    pub fn with_version<T: Into<String>>(mut self, version: T) -> Self {
+        self.version = Some(version.into());
+        self
+    }
+
+    /// Sets the endpoint of the agent OTLP intake used by the [`TraceExporter::Otlp`] exporter,
+    /// `http://localhost:4317` (gRPC) or `http://localhost:4318` (HTTP) by default.
+    #[cfg(feature = "otlp")]
+    pub fn with_otlp_endpoint<T: Into<String>>(mut self, otlp_endpoint: T) -> Self {
+        self.otlp_endpoint = Some(otlp_endpoint.into());
+        self
+    }
+
+    /// Sets the API key used by the [`TraceExporter::Agentless`] exporter.
+    #[cfg(feature = "agentless")]
+    pub fn with_api_key<T: Into<String>>(mut self, api_key: T) -> Self {
+        self.agentless.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Sets the Datadog site used by the [`TraceExporter::Agentless`] exporter,
+    /// `datadoghq.com` by default.
+    #[cfg(feature = "agentless")]
+    pub fn with_site<T: Into<String>>(mut self, site: T) -> Self {
+        self.agentless.site = site.into();
+        self
+    }
 
-    let dd_host = env::var("DD_AGENT_HOST").unwrap_or("localhost".to_string());
-    let dd_port = env::var("DD_AGENT_PORT")
+    /// Overrides the intake URL used by the [`TraceExporter::Agentless`] exporter,
+    /// e.g. to send traces through a proxy.
+    #[cfg(feature = "agentless")]
+    pub fn with_agentless_url<T: Into<String>>(mut self, url: T) -> Self {
+        self.agentless.url = Some(url.into());
+        self
+    }
+
+    /// Sets the version of the agent API used by the [`TraceExporter::Datadog`] exporter.
+    ///
+    /// By default, the most recent version supported by the agent is discovered on its `/info`
+    /// endpoint, falling back to v0.5 if it is unavailable (see [`crate::agent`]).
+    pub fn with_api_version(mut self, api_version: ApiVersion) -> Self {
+        self.api_version = Some(api_version);
+        self
+    }
+
+    /// Enables the computation of APM stats by the tracer, sent to the agent `/v0.6/stats`
+    /// endpoint, so unsampled traces can be dropped without skewing trace metrics.
+    ///
+    /// It is disabled again if the agent `/info` endpoint doesn't list `/v0.6/stats`.
+    ///
+    /// Only supported by the [`TraceExporter::Datadog`] exporter.
+    pub fn with_stats_computation(mut self, enabled: bool) -> Self {
+        self.stats_computation = enabled;
+        self
+    }
+
+    /// Sets the exporter used to send spans, [`TraceExporter::Datadog`] by default.
+    pub fn with_exporter(mut self, exporter: TraceExporter) -> Self {
+        self.exporter = exporter;
+        self
+    }
+
+    /// Sets the Datadog agent endpoint, `http://localhost:8126` by default.
+    pub fn with_agent_endpoint<T: Into<String>>(mut self, agent_endpoint: T) -> Self {
+        self.agent_endpoint = agent_endpoint.into();
+        self
+    }
+
+    /// Sets the function computing the Datadog operation name of spans.
+    ///
+    /// It is not called for spans with an explicit `operation.name` field.
+    /// See [`crate::mapping`] for the default mapping.
+    pub fn with_name_mapping<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a SpanData) -> Cow<'a, str> + Send + Sync + 'static,
+    {
+        self.mapping = self.mapping.with_name(Arc::new(f));
+        self
+    }
+
+    /// Sets the function computing the Datadog resource of spans.
+    ///
+    /// It is not called for spans with an explicit `resource.name` field.
+    /// See [`crate::mapping`] for the default mapping.
+    pub fn with_resource_mapping<F>(mut self, f: F) -> Self
+    where
+        F: for<'a> Fn(&'a SpanData) -> Cow<'a, str> + Send + Sync + 'static,
+    {
+        self.mapping = self.mapping.with_resource(Arc::new(f));
+        self
+    }
+
+    /// Returns the configuration of the tracer, logged at startup by [`crate::init()`].
+    pub fn startup_configuration(&self) -> StartupConfiguration {
+        let agent_url = match self.exporter {
+            #[cfg(feature = "agentless")]
+            TraceExporter::Agentless => None,
+            _ => Some(self.agent_endpoint.clone()),
+        };
+        StartupConfiguration {
+            date: chrono::Utc::now().to_rfc3339(),
+            tracer_version: env!("CARGO_PKG_VERSION"),
+            lang: "rust",
+            service: self.service_name.clone(),
+            env: self.env.clone(),
+            version: self.version.clone(),
+            agent_url,
+            exporter: self.exporter.to_string(),
+            api_version: self
+                .api_version
+                .map_or_else(|| "auto".to_string(), |version| version.to_string()),
+            sampler: "always_on",
+            propagation_styles: vec!["datadog"],
+            stats_computation_enabled: self.stats_computation,
+        }
+    }
+
+    /// Builds the tracer, installing its provider and the Datadog propagator globally.
+    pub fn build(self) -> TraceResult<Tracer> {
+        self.build_with_discovery().map(|(tracer, _)| tracer)
+    }
+
+    /// Same as [`TracerBuilder::build`], also returning the discovery of the agent features of
+    /// the Datadog exporter, whose result is logged at startup.
+    pub(crate) fn build_with_discovery(self) -> TraceResult<(Tracer, Option<AgentDiscovery>)> {
+        // disabling connection reuse with dd-agent to avoid "connection closed from server" errors
+        let dd_http_client = reqwest::ClientBuilder::new()
+            .pool_idle_timeout(Duration::from_millis(1))
+            .build()
+            .expect("Could not init datadog http_client");
+
+        // The resource is left empty so the SDK does not assign a service name, unless
+        // exporting over OTLP where the unified service tags are resource attributes.
+        let resource = match self.exporter {
+            #[cfg(feature = "otlp")]
+            TraceExporter::Otlp(_) => crate::otlp::resource(
+                &self.service_name,
+                self.env.as_deref(),
+                self.version.as_deref(),
+            ),
+            _ => Resource::empty(),
+        };
+
+        // the provider is built here, instead of using `install_batch`, to register our own span
+        // processors.
+        let provider_builder = TracerProvider::builder()
+            .with_span_processor(DatadogSpanProcessor)
+            .with_config(
+                trace::Config::default()
+                    .with_sampler(Sampler::AlwaysOn)
+                    .with_id_generator(RandomIdGenerator::default())
+                    .with_resource(resource),
+            );
+
+        let mut discovery = None;
+        let provider = match self.exporter {
+            TraceExporter::Datadog => {
+                let model_config = ModelConfig {
+                    service: self.service_name,
+                    env: self.env,
+                    version: self.version,
+                };
+                // the configured features are used until the agent ones are discovered
+                let features: SharedFeatures = Arc::new(RwLock::new(AgentFeatures::resolve(
+                    self.api_version,
+                    self.stats_computation,
+                    None,
+                )));
+                let agent_discovery = agent::discover(
+                    dd_http_client.clone(),
+                    self.agent_endpoint.clone(),
+                    self.api_version,
+                    self.stats_computation,
+                    features.clone(),
+                );
+                opentelemetry_sdk::runtime::Tokio
+                    .spawn(Box::pin(agent_discovery.clone().map(|_| ())));
+                discovery = Some(agent_discovery.clone());
+                let exporter = DatadogExporter::new(
+                    dd_http_client.clone(),
+                    &self.agent_endpoint,
+                    features.clone(),
+                    model_config.clone(),
+                    self.mapping.clone(),
+                )
+                .with_discovery(agent_discovery);
+                if self.stats_computation {
+                    let batch =
+                        BatchSpanProcessor::builder(exporter, opentelemetry_sdk::runtime::Tokio)
+                            .build();
+                    provider_builder.with_span_processor(StatsProcessor::new(
+                        batch,
+                        dd_http_client,
+                        &self.agent_endpoint,
+                        features,
+                        model_config,
+                        self.mapping,
+                    ))
+                } else {
+                    provider_builder
+                        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+                }
+            }
+            TraceExporter::OpentelemetryDatadog => {
+                let mut pipeline = opentelemetry_datadog::new_pipeline()
+                    .with_http_client(dd_http_client)
+                    .with_service_name(self.service_name)
+                    .with_api_version(opentelemetry_datadog::ApiVersion::Version05)
+                    .with_agent_endpoint(self.agent_endpoint)
+                    // the mapped values are recorded as attributes by the `MappingExporter`
+                    .with_name_mapping(|span, _| {
+                        attribute(span, OPERATION_NAME_KEY).unwrap_or_default()
+                    })
+                    .with_resource_mapping(|span, _| {
+                        attribute(span, RESOURCE_NAME_KEY).unwrap_or(span.name.as_ref())
+                    });
+                if let Some(env) = self.env {
+                    pipeline = pipeline.with_env(env);
+                }
+                if let Some(version) = self.version {
+                    pipeline = pipeline.with_version(version);
+                }
+                let exporter = MappingExporter::new(pipeline.build_exporter()?, self.mapping);
+                provider_builder.with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            }
+            // the agent honors the `operation.name`, `resource.name` and `span.type` attributes
+            // recorded by the `MappingExporter`
+            #[cfg(feature = "otlp")]
+            TraceExporter::Otlp(protocol) => {
+                let endpoint = self
+                    .otlp_endpoint
+                    .unwrap_or_else(|| format!("http://localhost:{}", protocol.default_port()));
+                let exporter = MappingExporter::new(
+                    crate::otlp::build_exporter(protocol, &endpoint)?,
+                    self.mapping,
+                );
+                provider_builder.with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            }
+            #[cfg(feature = "agentless")]
+            TraceExporter::Agentless => {
+                let api_key = self.agentless.api_key.ok_or_else(|| {
+                    <&str as Into<TraceError>>::into("missing DD_API_KEY for agentless export")
+                })?;
+                let url = self
+                    .agentless
+                    .url
+                    .unwrap_or_else(|| intake_url(&self.agentless.site));
+                let client = reqwest::ClientBuilder::new()
+                    .timeout(Duration::from_secs(10))
+                    .build()
+                    .map_err(|err| TraceError::from(crate::exporter::Error::from(err)))?;
+                let exporter = AgentlessExporter::new(
+                    client,
+                    url,
+                    api_key,
+                    ModelConfig {
+                        service: self.service_name,
+                        env: self.env,
+                        version: self.version,
+                    },
+                    self.mapping,
+                )
+                .with_export_timeout(batch_export_timeout());
+                provider_builder.with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+            }
+        }
+        .build();
+
+        // the instrumentation library name is used as the default Datadog operation name
+        let tracer = provider.tracer("opentelemetry-datadog");
+        let _ = global::set_tracer_provider(provider);
+
+        global::set_text_map_propagator(DatadogPropagator::default());
+
+        Ok((tracer, discovery))
+    }
+}
+
+// the export timeout of the batch span processor, read from the same environment variables
+#[cfg(feature = "agentless")]
+fn batch_export_timeout() -> Duration {
+    env::var("OTEL_BSP_EXPORT_TIMEOUT")
+        .or_else(|_| env::var("OTEL_BSP_EXPORT_TIMEOUT_MILLIS"))
         .ok()
-        .and_then(|it| it.parse::<i32>().ok())
-        .unwrap_or(8126);
-
-    // disabling connection reuse with dd-agent to avoid "connection closed from server" errors
-    let dd_http_client = reqwest::ClientBuilder::new()
-        .pool_idle_timeout(Duration::from_millis(1))
-        .build()
-        .expect("Could not init datadog http_client");
-
-    let tracer = opentelemetry_datadog::new_pipeline()
-        .with_http_client(dd_http_client)
-        .with_service_name(service_name)
-        .with_api_version(ApiVersion::Version05)
-        .with_agent_endpoint(format!("http://{dd_host}:{dd_port}"))
-        .with_trace_config(
+        .and_then(|timeout| timeout.parse().ok())
+        .map_or(DEFAULT_EXPORT_TIMEOUT, Duration::from_millis)
+}
+
+/// The builder of a provider recording spans without exporting them, to which processors can
+/// be added.
+pub(crate) fn local_provider_builder() -> trace::Builder {
+    TracerProvider::builder()
+        .with_span_processor(DatadogSpanProcessor)
+        .with_config(
             trace::Config::default()
                 .with_sampler(Sampler::AlwaysOn)
-                .with_id_generator(RandomIdGenerator::default()),
+                .with_id_generator(RandomIdGenerator::default())
+                .with_resource(Resource::empty()),
         )
-        .install_batch(opentelemetry_sdk::runtime::Tokio);
+}
+
+/// Builds a tracer which doesn't export spans, only generating the IDs injected in logs and
+/// propagated to downstream services, installing its provider and the Datadog propagator
+/// globally.
+pub(crate) fn build_local_tracer() -> Tracer {
+    let provider = local_provider_builder().build();
+
+    let tracer = provider.tracer("opentelemetry-datadog");
+    let _ = global::set_tracer_provider(provider);
 
     global::set_text_map_propagator(DatadogPropagator::default());
 
     tracer
 }
 
+pub fn build_tracer() -> TraceResult<Tracer> {
+    TracerBuilder::from_env()?.build()
+}
+
 pub fn build_layer<S>() -> TraceResult<OpenTelemetryLayer<S, Tracer>>
 where
     Tracer: opentelemetry::trace::Tracer + PreSampledTracer + 'static,
@@ -0,0 +1,521 @@
+//! Log shipping to the Datadog HTTP logs intake.
+//!
+//! Sends logs directly to `https://http-intake.logs.<DD_SITE>/api/v2/logs`, authenticated with
+//! `DD_API_KEY`, for deployments without an agent tailing stdout.
+//!
+//! The JSON lines of the [`crate::formatter::DatadogFormatter`] are batched in payloads of at
+//! most 1000 entries and 5 MiB, gzip compressed and retried with an exponential backoff on
+//! network errors, `408`, `429` and `5xx` responses. Lines which are not JSON objects are sent
+//! as the `message` of an entry.
+//!
+//! Batches are sent by a thread of the writer, once they are full or their first line is older
+//! than the flush interval, even if no more lines are written. The last batch is sent without
+//! retries when the writer is dropped, once the [`super::LogGuard`] returned by `init` has
+//! stopped the worker of the non-blocking writer, and the guard waits for it to be sent.
+//!
+//! Failed requests are reported to the OpenTelemetry error handler.
+use std::env;
+use std::fmt::{Debug, Formatter};
+use std::io::{self, Write};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use opentelemetry::global::Error as OtelError;
+use tokio::runtime::Runtime;
+
+use crate::http::{gzip, post_gzip, PostError};
+use crate::telemetry::handle_log_writer_error;
+
+const DATADOG_API_KEY_HEADER: &str = "DD-API-KEY";
+
+/// Maximum number of entries of a payload.
+pub const MAX_BATCH_ENTRIES: usize = 1000;
+/// Maximum size of an uncompressed payload.
+pub const MAX_BATCH_SIZE: usize = 5 * 1024 * 1024;
+/// Default number of retries of a failed request.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+/// Default maximum age of a batch before it is sent.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// maximum time spent sending a batch, retries included
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+// maximum time spent sending the last batch, within the shutdown timeout of the guard
+const LAST_BATCH_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Returns the logs intake URL of a Datadog site (e.g. `datadoghq.eu`).
+pub fn intake_url(site: &str) -> String {
+    format!("https://http-intake.logs.{site}/api/v2/logs")
+}
+
+/// Configuration of the logs shipped to the Datadog logs intake.
+///
+/// ```no_run
+/// use datadog_tracing::writer::{LogWriter, LogsIntake};
+///
+/// let intake = LogsIntake::new("my-api-key")
+///     .with_site("datadoghq.eu")
+///     .with_service("my-service");
+/// let (_guard, _shutdown) =
+///     datadog_tracing::init::init_with_writer(LogWriter::Intake(intake), Default::default())
+///         .unwrap();
+/// ```
+#[derive(Clone)]
+pub struct LogsIntake {
+    url: String,
+    api_key: String,
+    service: Option<String>,
+    tags: Vec<String>,
+    max_retries: u32,
+    flush_interval: Duration,
+}
+
+impl Debug for LogsIntake {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogsIntake")
+            .field("url", &self.url)
+            .field("service", &self.service)
+            .field("tags", &self.tags)
+            .field("max_retries", &self.max_retries)
+            .field("flush_interval", &self.flush_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogsIntake {
+    /// Ships logs to the `datadoghq.com` site, authenticated with `api_key`.
+    pub fn new<T: Into<String>>(api_key: T) -> Self {
+        LogsIntake {
+            url: intake_url("datadoghq.com"),
+            api_key: api_key.into(),
+            service: None,
+            tags: Vec::new(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+        }
+    }
+
+    /// Reads the configuration from the `DD_API_KEY`, `DD_SITE`, `DD_LOGS_INTAKE_URL`,
+    /// `DD_SERVICE`, `DD_ENV` and `DD_VERSION` environment variables.
+    pub fn from_env() -> io::Result<Self> {
+        let api_key = env::var("DD_API_KEY")
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "missing DD_API_KEY"))?;
+        let mut intake = LogsIntake::new(api_key);
+        if let Ok(site) = env::var("DD_SITE") {
+            intake = intake.with_site(&site);
+        }
+        if let Ok(url) = env::var("DD_LOGS_INTAKE_URL") {
+            intake = intake.with_url(url);
+        }
+        if let Ok(service) = env::var("DD_SERVICE") {
+            intake = intake.with_service(service);
+        }
+        for (key, tag) in [("DD_ENV", "env"), ("DD_VERSION", "version")] {
+            if let Ok(value) = env::var(key) {
+                intake = intake.with_tag(format!("{tag}:{value}"));
+            }
+        }
+        Ok(intake)
+    }
+
+    /// Ships logs to the given Datadog site, e.g. `datadoghq.eu`.
+    pub fn with_site(mut self, site: &str) -> Self {
+        self.url = intake_url(site);
+        self
+    }
+
+    /// Overrides the intake URL, e.g. to go through a proxy.
+    pub fn with_url<T: Into<String>>(mut self, url: T) -> Self {
+        self.url = url.into();
+        self
+    }
+
+    /// Sets the service of the entries without a `service` attribute.
+    pub fn with_service<T: Into<String>>(mut self, service: T) -> Self {
+        self.service = Some(service.into());
+        self
+    }
+
+    /// Adds a `key:value` tag to all the entries.
+    pub fn with_tag<T: Into<String>>(mut self, tag: T) -> Self {
+        self.tags.push(tag.into());
+        self
+    }
+
+    /// Sets the number of retries of a failed request, 3 by default.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the maximum age of a batch before it is sent, 1 second by default.
+    pub fn with_flush_interval(mut self, flush_interval: Duration) -> Self {
+        self.flush_interval = flush_interval;
+        self
+    }
+
+    /// Builds the writer batching the lines written to it, and spawns the thread sending them.
+    pub fn into_writer(self) -> io::Result<LogsIntakeWriter> {
+        let (entries, received) = mpsc::sync_channel(MAX_BATCH_ENTRIES);
+        let sender = BatchSender {
+            intake: self.clone(),
+            client: None,
+            batch: Vec::new(),
+            entries: 0,
+            batch_start: None,
+        };
+        let thread = std::thread::Builder::new()
+            .name("datadog-logs-intake".to_string())
+            .spawn(move || sender.run(received))?;
+        Ok(LogsIntakeWriter {
+            intake: self,
+            partial_line: Vec::new(),
+            entries: Some(entries),
+            sender: Some(thread),
+        })
+    }
+}
+
+/// Writer sending the log lines written to it to the Datadog logs intake, see the
+/// [module documentation](self).
+///
+/// Writing blocks while the sending thread is busy with a full queue of entries, so it is meant
+/// to be wrapped by a non-blocking writer.
+pub struct LogsIntakeWriter {
+    intake: LogsIntake,
+    partial_line: Vec<u8>,
+    // closed when the writer is dropped, to stop the sending thread
+    entries: Option<mpsc::SyncSender<Vec<u8>>>,
+    sender: Option<JoinHandle<()>>,
+}
+
+impl Debug for LogsIntakeWriter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogsIntakeWriter")
+            .field("intake", &self.intake)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogsIntakeWriter {
+    fn push_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let entry = if line.starts_with(b"{") {
+            line.to_vec()
+        } else {
+            let message = String::from_utf8_lossy(line);
+            serde_json::to_vec(&serde_json::json!({ "message": message }))?
+        };
+        // the array brackets and the separator
+        if entry.len() + 3 > MAX_BATCH_SIZE {
+            handle_log_writer_error(OtelError::Other(format!(
+                "dropping a log entry of {} bytes, larger than the Datadog intake payloads",
+                entry.len()
+            )));
+            return Ok(());
+        }
+        self.entries
+            .as_ref()
+            .and_then(|entries| entries.send(entry).ok())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::BrokenPipe, "logs intake stopped"))
+    }
+}
+
+impl Write for LogsIntakeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut lines = buf.split_inclusive(|byte| *byte == b'\n').peekable();
+        while let Some(line) = lines.next() {
+            if !line.ends_with(b"\n") && lines.peek().is_none() {
+                // completed by the next write
+                self.partial_line.extend_from_slice(line);
+            } else if self.partial_line.is_empty() {
+                self.push_line(line)?;
+            } else {
+                let mut partial_line = std::mem::take(&mut self.partial_line);
+                partial_line.extend_from_slice(line);
+                self.push_line(&partial_line)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    // batches are sent by the sending thread when they are full or old enough
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogsIntakeWriter {
+    fn drop(&mut self) {
+        // the sending thread sends the last batch once the queue is closed
+        self.entries = None;
+        if let Some(sender) = self.sender.take() {
+            let _ = sender.join();
+        }
+    }
+}
+
+// batches the entries of the writer and sends them, on its own thread
+struct BatchSender {
+    intake: LogsIntake,
+    // created on the first request
+    client: Option<(Runtime, reqwest::Client)>,
+    // the entries of the JSON array of the payload, separated by commas
+    batch: Vec<u8>,
+    entries: usize,
+    // when the first entry of the batch was received
+    batch_start: Option<Instant>,
+}
+
+impl BatchSender {
+    fn run(mut self, entries: mpsc::Receiver<Vec<u8>>) {
+        loop {
+            // an idle batch is sent once it is older than the flush interval
+            let received = match self.batch_start {
+                Some(start) => {
+                    let timeout = self.intake.flush_interval.saturating_sub(start.elapsed());
+                    if timeout.is_zero() {
+                        self.send_batch(self.intake.max_retries, SEND_TIMEOUT);
+                        continue;
+                    }
+                    entries.recv_timeout(timeout)
+                }
+                None => entries
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(entry) => self.push_entry(entry),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        // not retried, to bound the time taken by the shutdown
+        self.send_batch(0, LAST_BATCH_TIMEOUT);
+    }
+
+    fn push_entry(&mut self, entry: Vec<u8>) {
+        if self.entries == MAX_BATCH_ENTRIES || self.batch.len() + entry.len() + 3 > MAX_BATCH_SIZE
+        {
+            // the entry is still added to the next batch
+            self.send_batch(self.intake.max_retries, SEND_TIMEOUT);
+        }
+        if self.entries > 0 {
+            self.batch.push(b',');
+        } else {
+            self.batch_start = Some(Instant::now());
+        }
+        self.batch.extend_from_slice(&entry);
+        self.entries += 1;
+    }
+
+    // sends the batch, reporting failures
+    fn send_batch(&mut self, max_retries: u32, timeout: Duration) {
+        if let Err(err) = self.try_send_batch(max_retries, timeout) {
+            handle_log_writer_error(OtelError::Other(format!(
+                "failed to send logs to the Datadog intake: {err}"
+            )));
+        }
+    }
+
+    fn try_send_batch(&mut self, max_retries: u32, timeout: Duration) -> io::Result<()> {
+        if self.entries == 0 {
+            return Ok(());
+        }
+        let payload = gzip(&[b"[", &self.batch, b"]"])?;
+        // the batch is dropped even if it can't be sent, so that it doesn't grow unbounded
+        self.batch.clear();
+        self.entries = 0;
+        self.batch_start = None;
+
+        if self.client.is_none() {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()?;
+            let client = reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .map_err(io::Error::other)?;
+            self.client = Some((runtime, client));
+        }
+        let (runtime, client) = self.client.as_ref().expect("the client is created above");
+        let deadline = Instant::now() + timeout;
+        runtime
+            .block_on(send_payload(
+                client,
+                &self.intake,
+                payload,
+                max_retries,
+                deadline,
+            ))
+            .map_err(io::Error::other)
+    }
+}
+
+async fn send_payload(
+    client: &reqwest::Client,
+    intake: &LogsIntake,
+    payload: Vec<u8>,
+    max_retries: u32,
+    deadline: Instant,
+) -> Result<(), PostError> {
+    let mut query = vec![("ddsource", "rust".to_string())];
+    if let Some(service) = &intake.service {
+        query.push(("service", service.clone()));
+    }
+    if !intake.tags.is_empty() {
+        query.push(("ddtags", intake.tags.join(",")));
+    }
+
+    let request = || {
+        client
+            .post(&intake.url)
+            .query(&query)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(DATADOG_API_KEY_HEADER, &intake.api_key)
+    };
+    post_gzip(request, &payload, max_retries, deadline, |_| {}).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::mock::{self, Requests};
+    use crate::writer::NonBlockingConfig;
+    use hyper::StatusCode;
+    use serde_json::Value;
+
+    fn mock_intake(statuses: Vec<StatusCode>) -> (String, Requests) {
+        mock::mock_intake("/api/v2/logs", statuses)
+    }
+
+    // the number of entries of every batch received
+    fn batches(requests: &Requests) -> Vec<usize> {
+        requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| {
+                let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+                entries.len()
+            })
+            .collect()
+    }
+
+    fn intake(url: String) -> LogsIntake {
+        LogsIntake::new("my-api-key")
+            .with_url(url)
+            .with_service("my-service")
+            .with_tag("env:test")
+            .with_tag("version:1.0")
+            .with_flush_interval(Duration::from_secs(60))
+    }
+
+    // waits for the intake to receive `count` requests
+    fn wait_for_requests(requests: &Requests, count: usize) {
+        let start = Instant::now();
+        while requests.lock().unwrap().len() < count && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ship_logs() {
+        let (url, requests) = mock_intake(vec![StatusCode::SERVICE_UNAVAILABLE]);
+
+        let mut writer = intake(url)
+            .with_flush_interval(Duration::from_millis(50))
+            .into_writer()
+            .unwrap();
+        let sent = requests.clone();
+        tokio::task::spawn_blocking(move || {
+            writer
+                .write_all(b"{\"message\":\"first\"}\n{\"mess")
+                .unwrap();
+            writer
+                .write_all(b"age\":\"second\"}\nplain text\n")
+                .unwrap();
+            wait_for_requests(&sent, 2);
+        })
+        .await
+        .unwrap();
+
+        let requests = requests.lock().unwrap();
+        // retried once
+        assert_eq!(requests.len(), 2);
+        let request = &requests[1];
+        assert_eq!(
+            request.uri.query(),
+            Some("ddsource=rust&service=my-service&ddtags=env%3Atest%2Cversion%3A1.0")
+        );
+        assert_eq!(request.headers["dd-api-key"], "my-api-key");
+        assert_eq!(request.headers["content-encoding"], "gzip");
+        let entries: Vec<Value> = serde_json::from_slice(&request.body).unwrap();
+        let messages: Vec<_> = entries.iter().map(|entry| &entry["message"]).collect();
+        assert_eq!(messages, vec!["first", "second", "plain text"]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_flush_interval() {
+        let (url, requests) = mock_intake(vec![]);
+
+        let mut writer = intake(url)
+            .with_flush_interval(Duration::from_millis(200))
+            .into_writer()
+            .unwrap();
+        let sent = requests.clone();
+        tokio::task::spawn_blocking(move || {
+            for message in ["first", "second"] {
+                writeln!(writer, "{{\"message\":\"{message}\"}}").unwrap();
+            }
+            assert!(sent.lock().unwrap().is_empty());
+            // the idle batch is sent by the timer, without further writes nor flushes
+            wait_for_requests(&sent, 1);
+            assert_eq!(batches(&sent), vec![2]);
+            // the last batch is sent by the drop of the writer
+            writeln!(writer, "{{\"message\":\"third\"}}").unwrap();
+            drop(writer);
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(batches(&requests), vec![2, 1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_batch_limits() {
+        let (url, requests) = mock_intake(vec![StatusCode::BAD_REQUEST]);
+
+        let mut writer = intake(url).with_max_retries(0).into_writer().unwrap();
+        tokio::task::spawn_blocking(move || {
+            // the first batch is rejected, and not retried, without failing the writes
+            for i in 0..MAX_BATCH_ENTRIES + 1 {
+                writeln!(writer, "{{\"message\":\"{i}\"}}").unwrap();
+            }
+            drop(writer);
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(batches(&requests), vec![MAX_BATCH_ENTRIES, 1]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_guard_waits_for_last_batch() {
+        let (url, requests) = mock_intake(vec![]);
+
+        let writer = intake(url).into_writer().unwrap();
+        let (mut non_blocking, guard, _) = NonBlockingConfig::default().build(writer);
+        tokio::task::spawn_blocking(move || {
+            writeln!(non_blocking, "{{\"message\":\"last\"}}").unwrap();
+            drop(guard);
+            // sent before the guard is dropped
+            assert_eq!(batches(&requests), vec![1]);
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,623 @@
+//! Destinations of the logs written by [`crate::init()`].
+//!
+//! Logs are written to stdout by default, or to stderr, rolling files picked up by the file
+//! tailing of the Datadog agent, or any `MakeWriter`. They always go through the non-blocking
+//! writer of `tracing_appender`, flushed when the [`LogGuard`] returned by `init` is dropped.
+//!
+//! | Environment variable | Default | Description                                              |
+//! |----------------------|---------|----------------------------------------------------------|
+//! | `LOG_OUTPUT`         | stdout  | `stdout`, `stderr`, `file` or `intake`                   |
+//! | `LOG_FILE`           |         | Path of the log file, required with `LOG_OUTPUT=file`    |
+//! | `LOG_FILE_ROTATION`  | daily   | `hourly`, `daily`, `size` or `never`                     |
+//! | `LOG_FILE_MAX_SIZE`  | 100 MiB | Size in bytes of the files rotated by size               |
+//! | `LOG_FILE_MAX_FILES` |         | Number of files kept, including the current one          |
+//! | `LOG_BUFFERED_LINES_LIMIT` | 128000 | Lines buffered by the non-blocking writer          |
+//! | `LOG_LOSSY`          | true    | Drops lines when the buffer is full, instead of blocking |
+//!
+//! With the `logs-intake` feature, logs can also be shipped to the Datadog HTTP logs intake,
+//! configured by [`LogsIntake::from_env`].
+//!
+//! Lines dropped by the lossy non-blocking writer are counted, and reported periodically by a
+//! warning written to stderr and the `datadog.tracer.logs.dropped` DogStatsD count, see
+//! [`DroppedLinesReporter`].
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use tracing_appender::non_blocking::{
+    ErrorCounter, NonBlocking, NonBlockingBuilder, WorkerGuard, DEFAULT_BUFFERED_LINES_LIMIT,
+};
+use tracing_appender::rolling::{self, RollingFileAppender};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::MakeWriter;
+
+#[cfg(feature = "metrics")]
+use crate::metrics::DogStatsD;
+
+#[cfg(feature = "logs-intake")]
+mod intake;
+#[cfg(feature = "logs-intake")]
+pub use intake::{intake_url, LogsIntake, LogsIntakeWriter};
+
+const DEFAULT_MAX_SIZE: u64 = 100 * 1024 * 1024;
+const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Maximum time the [`LogGuard`] waits for the destination to be closed, once the non-blocking
+/// writer is stopped.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Destination of the logs.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub enum LogWriter {
+    #[default]
+    Stdout,
+    Stderr,
+    /// Files rotated by time or size.
+    File(RollingFile),
+    /// Any `MakeWriter`, see [`LogWriter::custom`].
+    Custom(BoxMakeWriter),
+    /// The Datadog HTTP logs intake.
+    #[cfg(feature = "logs-intake")]
+    Intake(LogsIntake),
+}
+
+impl LogWriter {
+    /// Uses any `MakeWriter`, called for every log line.
+    pub fn custom<M>(make_writer: M) -> Self
+    where
+        M: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        LogWriter::Custom(BoxMakeWriter::new(make_writer))
+    }
+
+    /// Reads the destination from the `LOG_OUTPUT`, `LOG_FILE`, `LOG_FILE_ROTATION`,
+    /// `LOG_FILE_MAX_SIZE` and `LOG_FILE_MAX_FILES` environment variables.
+    pub fn from_env() -> io::Result<Self> {
+        match env::var("LOG_OUTPUT").as_deref() {
+            Err(_) | Ok("stdout") => Ok(LogWriter::Stdout),
+            Ok("stderr") => Ok(LogWriter::Stderr),
+            Ok("file") => {
+                let path = env::var("LOG_FILE")
+                    .map_err(|_| invalid_input("missing LOG_FILE".to_string()))?;
+                let rotation = match env::var("LOG_FILE_ROTATION").as_deref() {
+                    Err(_) | Ok("daily") => FileRotation::Daily,
+                    Ok("hourly") => FileRotation::Hourly,
+                    Ok("never") => FileRotation::Never,
+                    Ok("size") => FileRotation::Size(
+                        parse_env("LOG_FILE_MAX_SIZE")?.unwrap_or(DEFAULT_MAX_SIZE),
+                    ),
+                    Ok(rotation) => {
+                        return Err(invalid_input(format!(
+                            "unsupported log file rotation {rotation}"
+                        )))
+                    }
+                };
+                let file = RollingFile::new(path).with_rotation(rotation);
+                Ok(LogWriter::File(match parse_env("LOG_FILE_MAX_FILES")? {
+                    Some(max_files) => file.with_max_files(max_files),
+                    None => file,
+                }))
+            }
+            #[cfg(feature = "logs-intake")]
+            Ok("intake") => Ok(LogWriter::Intake(LogsIntake::from_env()?)),
+            Ok(output) => Err(invalid_input(format!("unsupported log output {output}"))),
+        }
+    }
+
+    // colors are only written to the terminal, unless disabled by `NO_COLOR`
+    pub(crate) fn ansi(&self) -> bool {
+        matches!(self, LogWriter::Stdout | LogWriter::Stderr)
+    }
+
+    /// Opens the destination, as the writer of the non-blocking worker.
+    pub(crate) fn into_writer(self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(match self {
+            LogWriter::Stdout => Box::new(io::stdout()),
+            LogWriter::Stderr => Box::new(io::stderr()),
+            LogWriter::File(file) => file.open()?,
+            LogWriter::Custom(make_writer) => Box::new(MakeWriterAdaptor(make_writer)),
+            #[cfg(feature = "logs-intake")]
+            LogWriter::Intake(intake) => Box::new(intake.into_writer()?),
+        })
+    }
+}
+
+/// Backpressure of the non-blocking writer: the number of buffered lines, and whether lines
+/// are dropped or the logging threads blocked when the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonBlockingConfig {
+    buffered_lines_limit: usize,
+    lossy: bool,
+}
+
+impl Default for NonBlockingConfig {
+    fn default() -> Self {
+        NonBlockingConfig {
+            buffered_lines_limit: DEFAULT_BUFFERED_LINES_LIMIT,
+            lossy: true,
+        }
+    }
+}
+
+impl NonBlockingConfig {
+    /// Reads the configuration from the `LOG_BUFFERED_LINES_LIMIT` and `LOG_LOSSY` environment
+    /// variables.
+    pub fn from_env() -> io::Result<Self> {
+        let mut config = NonBlockingConfig::default();
+        if let Some(limit) = parse_env("LOG_BUFFERED_LINES_LIMIT")? {
+            config.buffered_lines_limit = limit;
+        }
+        if let Some(lossy) = parse_env("LOG_LOSSY")? {
+            config.lossy = lossy;
+        }
+        Ok(config)
+    }
+
+    /// Sets the number of lines buffered before dropping or blocking, 128 000 by default.
+    pub fn with_buffered_lines_limit(mut self, buffered_lines_limit: usize) -> Self {
+        self.buffered_lines_limit = buffered_lines_limit;
+        self
+    }
+
+    /// Drops lines when the buffer is full, `true` by default. Otherwise, the threads logging
+    /// are blocked until the buffer has room.
+    pub fn with_lossy(mut self, lossy: bool) -> Self {
+        self.lossy = lossy;
+        self
+    }
+
+    pub(crate) fn is_lossy(&self) -> bool {
+        self.lossy
+    }
+
+    /// Builds the non-blocking writer, and a reference to its worker which is dropped when the
+    /// guard stops it.
+    pub(crate) fn build<T: Write + Send + 'static>(
+        &self,
+        writer: T,
+    ) -> (NonBlocking, LogGuard, Weak<()>) {
+        let alive = Arc::new(());
+        let worker = Arc::downgrade(&alive);
+        let (closed, closed_signal) = mpsc::channel();
+        let (non_blocking, guard) = NonBlockingBuilder::default()
+            .buffered_lines_limit(self.buffered_lines_limit)
+            .lossy(self.lossy)
+            .finish(WorkerWriter {
+                writer,
+                _alive: alive,
+                _closed: closed,
+            });
+        let guard = LogGuard {
+            worker: Some(guard),
+            closed: closed_signal,
+        };
+        (non_blocking, guard, worker)
+    }
+}
+
+/// Guard of the log writer returned by [`crate::init()`], to keep until the end of `main`.
+///
+/// Dropping it writes the lines buffered by the non-blocking writer and stops its worker, then
+/// waits at most [`SHUTDOWN_TIMEOUT`] for the destination to be closed, e.g. for the logs intake
+/// to send its last batch.
+#[must_use]
+#[derive(Debug)]
+pub struct LogGuard {
+    worker: Option<WorkerGuard>,
+    // disconnected once the writer of the worker is dropped
+    closed: mpsc::Receiver<()>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        drop(self.worker.take());
+        let _ = self.closed.recv_timeout(SHUTDOWN_TIMEOUT);
+    }
+}
+
+/// Reporter periodically warning about the lines dropped by the non-blocking writer since the
+/// previous report, and sending their count to DogStatsD.
+///
+/// The warning is written to stderr, as logging it would go through the full buffer of the
+/// writer. The `datadog.tracer.logs.dropped` count is the signal to monitor.
+#[derive(Debug, Clone)]
+pub struct DroppedLinesReporter {
+    counter: ErrorCounter,
+    #[cfg(feature = "metrics")]
+    client: Option<DogStatsD>,
+    interval: Duration,
+    worker: Option<Weak<()>>,
+}
+
+impl DroppedLinesReporter {
+    /// Reports the lines dropped by `writer`.
+    pub fn new(writer: &NonBlocking) -> Self {
+        DroppedLinesReporter {
+            counter: writer.error_counter(),
+            #[cfg(feature = "metrics")]
+            client: None,
+            interval: DEFAULT_REPORT_INTERVAL,
+            worker: None,
+        }
+    }
+
+    // stops reporting once the worker of the writer is stopped
+    pub(crate) fn with_worker(mut self, worker: Weak<()>) -> Self {
+        self.worker = Some(worker);
+        self
+    }
+
+    /// Sends the dropped lines as the `datadog.tracer.logs.dropped` count.
+    #[cfg(feature = "metrics")]
+    pub fn with_dogstatsd(mut self, client: DogStatsD) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the reporting interval, 10 seconds by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Spawns the reporting thread, so that logs can be reported without a Tokio runtime.
+    ///
+    /// With the writer of [`crate::init()`], the thread stops after a last report once the guard
+    /// is dropped. Otherwise, it runs until the end of the process.
+    pub fn spawn(self) -> io::Result<JoinHandle<()>> {
+        std::thread::Builder::new()
+            .name("datadog-dropped-logs".to_string())
+            .spawn(move || {
+                let mut previous = 0;
+                loop {
+                    std::thread::sleep(self.interval);
+                    previous = self.report(previous);
+                    if self.worker.as_ref().is_some_and(|w| w.strong_count() == 0) {
+                        break;
+                    }
+                }
+            })
+    }
+
+    // returns the total of dropped lines
+    fn report(&self, previous: usize) -> usize {
+        let dropped_lines = self.counter.dropped_lines();
+        let dropped = dropped_lines.saturating_sub(previous);
+        if dropped > 0 {
+            eprintln!(
+                "{dropped} log lines dropped by the non-blocking writer, its buffer was full"
+            );
+            #[cfg(feature = "metrics")]
+            if let Some(client) = &self.client {
+                client.count("datadog.tracer.logs.dropped", dropped as i64, &[]);
+            }
+        }
+        dropped_lines
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(key: &str) -> io::Result<Option<T>> {
+    env::var(key)
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| invalid_input(format!("invalid {key} {value}")))
+        })
+        .transpose()
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// When log files are rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FileRotation {
+    /// Every hour, to files suffixed with the date and hour, e.g. `app.log.2024-01-01-12`
+    Hourly,
+    /// Every day, to files suffixed with the date, e.g. `app.log.2024-01-01`
+    Daily,
+    /// When the file would exceed the size in bytes. The current file keeps its name, and the
+    /// previous ones are renamed `app.log.1` (the most recent), `app.log.2`…
+    Size(u64),
+    /// Never, always writing to the same file
+    Never,
+}
+
+/// Log files rotated by time or size, daily by default.
+///
+/// ```no_run
+/// use datadog_tracing::writer::{FileRotation, LogWriter, RollingFile};
+///
+/// let writer = LogWriter::File(
+///     RollingFile::new("/var/log/my-service/app.log")
+///         .with_rotation(FileRotation::Size(50 * 1024 * 1024))
+///         .with_max_files(5),
+/// );
+/// let (_guard, _shutdown) =
+///     datadog_tracing::init::init_with_writer(writer, Default::default()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RollingFile {
+    path: PathBuf,
+    rotation: FileRotation,
+    max_files: Option<usize>,
+}
+
+impl RollingFile {
+    /// Writes to `path`, suffixed with the date when rotated by time.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        RollingFile {
+            path: path.into(),
+            rotation: FileRotation::Daily,
+            max_files: None,
+        }
+    }
+
+    /// Sets when files are rotated, [`FileRotation::Daily`] by default.
+    pub fn with_rotation(mut self, rotation: FileRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Deletes the oldest files beyond `max_files`, including the current one. All files are
+    /// kept by default.
+    pub fn with_max_files(mut self, max_files: usize) -> Self {
+        self.max_files = Some(max_files);
+        self
+    }
+
+    fn open(self) -> io::Result<Box<dyn Write + Send>> {
+        let file_name = self
+            .path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| invalid_input(format!("invalid log file {}", self.path.display())))?
+            .to_string();
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let rotation = match self.rotation {
+            FileRotation::Hourly => rolling::Rotation::HOURLY,
+            FileRotation::Daily => rolling::Rotation::DAILY,
+            FileRotation::Never => rolling::Rotation::NEVER,
+            FileRotation::Size(max_size) => {
+                return Ok(Box::new(SizeRollingWriter::new(
+                    &directory,
+                    file_name,
+                    max_size,
+                    self.max_files,
+                )?))
+            }
+        };
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(file_name);
+        if let Some(max_files) = self.max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        let appender = builder.build(directory).map_err(io::Error::other)?;
+        Ok(Box::new(appender))
+    }
+}
+
+/// Writes to a file renamed with an index once it reaches its maximum size.
+struct SizeRollingWriter {
+    directory: PathBuf,
+    file_name: String,
+    max_size: u64,
+    max_files: Option<usize>,
+    file: File,
+    size: u64,
+}
+
+impl SizeRollingWriter {
+    fn new(
+        directory: &Path,
+        file_name: String,
+        max_size: u64,
+        max_files: Option<usize>,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let file = open_append(&directory.join(&file_name))?;
+        Ok(SizeRollingWriter {
+            directory: directory.to_path_buf(),
+            size: file.metadata()?.len(),
+            file_name,
+            max_size,
+            max_files,
+            file,
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        self.directory.join(format!("{}.{index}", self.file_name))
+    }
+
+    // the highest index of the rotated files
+    fn last_index(&self) -> io::Result<usize> {
+        let prefix = format!("{}.", self.file_name);
+        Ok(fs::read_dir(&self.directory)?
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name();
+                name.to_str()?.strip_prefix(&prefix)?.parse().ok()
+            })
+            .max()
+            .unwrap_or(0))
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let last = self.last_index()?;
+        for index in (1..=last).rev() {
+            not_found_ok(fs::rename(self.rotated(index), self.rotated(index + 1)))?;
+        }
+        let path = self.directory.join(&self.file_name);
+        not_found_ok(fs::rename(&path, self.rotated(1)))?;
+        if let Some(max_files) = self.max_files {
+            for index in max_files.max(1)..=last + 1 {
+                not_found_ok(fs::remove_file(self.rotated(index)))?;
+            }
+        }
+        self.file = open_append(&path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+// files may have been removed by someone else
+fn not_found_ok(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Write for SizeRollingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.roll()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+// the writer of the non-blocking worker, dropped when the worker stops, before the references
+// to the worker
+struct WorkerWriter<T> {
+    writer: T,
+    _alive: Arc<()>,
+    _closed: mpsc::Sender<()>,
+}
+
+impl<T: Write> Write for WorkerWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.writer.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// the non-blocking worker needs a single writer
+struct MakeWriterAdaptor(BoxMakeWriter);
+
+impl Write for MakeWriterAdaptor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.make_writer().write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.make_writer().write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.make_writer().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_dir(directory: &Path) -> Vec<(String, String)> {
+        let mut files: Vec<_> = fs::read_dir(directory)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let name = path.file_name().unwrap().to_str().unwrap().to_string();
+                (name, fs::read_to_string(path).unwrap())
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_dropped_lines_reporter() {
+        // a writer blocked until the end of the test
+        struct Blocked(std::sync::mpsc::Receiver<()>);
+
+        impl Write for Blocked {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let _ = self.0.recv();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (unblock, blocked) = std::sync::mpsc::channel();
+        let config = NonBlockingConfig::default().with_buffered_lines_limit(2);
+        let (mut writer, guard, worker) = config.build(Blocked(blocked));
+        let reporter = DroppedLinesReporter::new(&writer);
+        assert_eq!(reporter.report(0), 0);
+
+        for _ in 0..10 {
+            writer.write_all(b"line\n").unwrap();
+        }
+
+        // the worker may have taken one line before blocking
+        let dropped = reporter.report(0);
+        assert!((7..=8).contains(&dropped), "{dropped} lines dropped");
+        assert_eq!(reporter.report(dropped), dropped);
+
+        // the reporting thread stops with the worker
+        let reporter = reporter
+            .with_worker(worker)
+            .with_interval(Duration::from_millis(10));
+        let thread = reporter.spawn().unwrap();
+        drop(unblock);
+        drop(guard);
+        thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_size_rotation() {
+        let directory = env::temp_dir().join(format!("dd-size-rotation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        let file = RollingFile::new(directory.join("app.log"))
+            .with_rotation(FileRotation::Size(10))
+            .with_max_files(3);
+
+        let mut writer = LogWriter::File(file).into_writer().unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+
+        assert_eq!(
+            read_dir(&directory),
+            vec![
+                ("app.log".to_string(), "fourth\n".to_string()),
+                ("app.log.1".to_string(), "third\n".to_string()),
+                ("app.log.2".to_string(), "second\n".to_string()),
+            ]
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}